@@ -0,0 +1,246 @@
+use serde::{Deserialize, Deserializer};
+use std::env;
+
+use crate::error::ApiError;
+
+// Wallet addresses are the 32-byte wallet ID encoded as Bech32m (BIP 350) behind the network's
+// human-readable prefix, e.g. "cw1...". A mistyped address fails its checksum instead of naming a
+// wallet that does not exist. Wallet IDs stay hex internally, and legacy hex IDs are still accepted.
+pub const DEFAULT_ADDRESS_HRP: &str = "cw";
+
+const CHARSET: &[u8; 32] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+const BECH32M_CONST: u32 = 0x2bc8_30a3;
+const GENERATOR: [u32; 5] = [0x3b6a_57b2, 0x2650_8e6d, 0x1ea1_19fa, 0x3d42_33dd, 0x2a14_62b3];
+const CHECKSUM_LENGTH: usize = 6;
+const MAX_ADDRESS_LENGTH: usize = 90;
+const WALLET_ID_BYTES: usize = 32;
+
+pub fn address_hrp() -> String {
+    env::var("ADDRESS_HRP")
+        .map(|hrp| hrp.to_lowercase())
+        .unwrap_or_else(|_| DEFAULT_ADDRESS_HRP.to_string())
+}
+
+// Address of a hex wallet ID, or None for IDs that are not 32-byte hex such as "ZAKAT_POOL"
+pub fn encode(wallet_id: &str) -> Option<String> {
+    encode_with_hrp(&address_hrp(), wallet_id)
+}
+
+fn encode_with_hrp(hrp: &str, wallet_id: &str) -> Option<String> {
+    let bytes: [u8; WALLET_ID_BYTES] = hex::decode(wallet_id).ok()?.try_into().ok()?;
+    let mut data = convert_bits(&bytes, 8, 5, true)?;
+    data.extend(checksum(hrp, &data));
+
+    let mut address = format!("{}1", hrp);
+    address.extend(data.iter().map(|&d| CHARSET[d as usize] as char));
+    Some(address)
+}
+
+// Wallet ID named by an address or a legacy hex ID
+pub fn parse(input: &str) -> Result<String, ApiError> {
+    decode(input.trim()).map_err(|e| ApiError::BadRequest(format!("Invalid wallet address: {}", e)))
+}
+
+// For request fields holding a wallet ID: accepts either form and yields the hex wallet ID
+pub fn deserialize_wallet_id<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    let input = String::deserialize(deserializer)?;
+    decode(input.trim()).map_err(|e| serde::de::Error::custom(format!("invalid wallet address: {}", e)))
+}
+
+pub fn deserialize_optional_wallet_id<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<String>, D::Error> {
+    match Option::<String>::deserialize(deserializer)? {
+        Some(input) => decode(input.trim())
+            .map(Some)
+            .map_err(|e| serde::de::Error::custom(format!("invalid wallet address: {}", e))),
+        None => Ok(None),
+    }
+}
+
+fn decode(input: &str) -> Result<String, String> {
+    if input.len() == WALLET_ID_BYTES * 2 && input.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Ok(input.to_lowercase());
+    }
+    if input.len() > MAX_ADDRESS_LENGTH {
+        return Err("too long".to_string());
+    }
+    if input.chars().any(|c| c.is_ascii_lowercase()) && input.chars().any(|c| c.is_ascii_uppercase()) {
+        return Err("mixed case".to_string());
+    }
+
+    let input = input.to_lowercase();
+    let (hrp, data) = input
+        .rsplit_once('1')
+        .ok_or_else(|| "expected a Bech32m address or a 64-character hex wallet ID".to_string())?;
+    let expected_hrp = address_hrp();
+    if hrp != expected_hrp {
+        return Err(format!("prefix '{}' does not match this network's '{}'", hrp, expected_hrp));
+    }
+    let data = data
+        .bytes()
+        .map(|c| CHARSET.iter().position(|&d| d == c).map(|d| d as u8))
+        .collect::<Option<Vec<u8>>>()
+        .ok_or_else(|| "invalid character".to_string())?;
+    if data.len() < CHECKSUM_LENGTH || polymod(&[hrp_expand(hrp), data.clone()].concat()) != BECH32M_CONST {
+        return Err("checksum mismatch".to_string());
+    }
+
+    let payload = &data[..data.len() - CHECKSUM_LENGTH];
+    match convert_bits(payload, 5, 8, false) {
+        Some(bytes) if bytes.len() == WALLET_ID_BYTES => Ok(hex::encode(bytes)),
+        _ => Err("wrong length".to_string()),
+    }
+}
+
+fn polymod(values: &[u8]) -> u32 {
+    let mut chk: u32 = 1;
+    for &value in values {
+        let top = chk >> 25;
+        chk = ((chk & 0x01ff_ffff) << 5) ^ value as u32;
+        for (i, generator) in GENERATOR.iter().enumerate() {
+            if (top >> i) & 1 == 1 {
+                chk ^= generator;
+            }
+        }
+    }
+    chk
+}
+
+fn hrp_expand(hrp: &str) -> Vec<u8> {
+    let mut expanded: Vec<u8> = hrp.bytes().map(|c| c >> 5).collect();
+    expanded.push(0);
+    expanded.extend(hrp.bytes().map(|c| c & 31));
+    expanded
+}
+
+fn checksum(hrp: &str, data: &[u8]) -> Vec<u8> {
+    let values = [hrp_expand(hrp), data.to_vec(), vec![0; CHECKSUM_LENGTH]].concat();
+    let modulus = polymod(&values) ^ BECH32M_CONST;
+    (0..CHECKSUM_LENGTH)
+        .map(|i| ((modulus >> (5 * (CHECKSUM_LENGTH - 1 - i))) & 31) as u8)
+        .collect()
+}
+
+// Regroups bits, e.g. bytes into 5-bit Bech32 characters and back
+fn convert_bits(data: &[u8], from: u32, to: u32, pad: bool) -> Option<Vec<u8>> {
+    let mut acc: u32 = 0;
+    let mut bits: u32 = 0;
+    let max = (1 << to) - 1;
+    let mut out = Vec::new();
+    for &value in data {
+        if (value as u32) >> from != 0 {
+            return None;
+        }
+        acc = (acc << from) | value as u32;
+        bits += from;
+        while bits >= to {
+            bits -= to;
+            out.push(((acc >> bits) & max) as u8);
+        }
+    }
+    if pad {
+        if bits > 0 {
+            out.push(((acc << (to - bits)) & max) as u8);
+        }
+    } else if bits >= from || ((acc << (to - bits)) & max) != 0 {
+        return None;
+    }
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WALLET_ID: &str = "3f2a9c0d5e7b81f4a6c2d9e0b3f17a8c5d4e6f709182a3b4c5d6e7f8091a2b3c";
+
+    // Checks only the Bech32m checksum of any string, as the BIP-350 vectors use arbitrary prefixes
+    fn has_valid_checksum(input: &str) -> bool {
+        let input = input.to_lowercase();
+        let Some((hrp, data)) = input.rsplit_once('1') else {
+            return false;
+        };
+        let data: Option<Vec<u8>> = data
+            .bytes()
+            .map(|c| CHARSET.iter().position(|&d| d == c).map(|d| d as u8))
+            .collect();
+        match data {
+            Some(data) if !hrp.is_empty() && data.len() >= CHECKSUM_LENGTH => {
+                polymod(&[hrp_expand(hrp), data].concat()) == BECH32M_CONST
+            }
+            _ => false,
+        }
+    }
+
+    #[test]
+    fn accepts_bip350_valid_vectors() {
+        for vector in [
+            "A1LQFN3A",
+            "a1lqfn3a",
+            "an83characterlonghumanreadablepartthatcontainsthetheexcludedcharactersbioandnumber11sg7hg6",
+            "abcdef1l7aum6echk45nj3s0wdvt2fg8x9yrzpqzd3ryx",
+            "11llllllllllllllllllllllllllllllllllllllllllllllllllllllllllllllllllllllllllllllllllludsr8",
+            "split1checkupstagehandshakeupstreamerranterredcaperredlc445v",
+            "?1v759aa",
+        ] {
+            assert!(has_valid_checksum(vector), "{}", vector);
+
+            // The generated checksum is the one in the vector
+            let lower = vector.to_lowercase();
+            let (hrp, data) = lower.rsplit_once('1').unwrap();
+            let values: Vec<u8> = data.bytes().map(|c| CHARSET.iter().position(|&d| d == c).unwrap() as u8).collect();
+            let (payload, expected) = values.split_at(values.len() - CHECKSUM_LENGTH);
+            assert_eq!(checksum(hrp, payload), expected, "{}", vector);
+        }
+    }
+
+    #[test]
+    fn rejects_bip350_invalid_vectors() {
+        for vector in [
+            "M1VUXWEZ", // checksum calculated with uninverted constant
+            "16plkw9", // empty prefix
+            "1p2gdwpf", // empty prefix
+            "qyrz8wqd2c9m", // no separator
+            "y1b0jsk6g", // invalid data character
+            "lt1igcx5c0", // invalid data character
+            "in1muywd", // checksum too short
+            "a12uel5l", // valid Bech32, not Bech32m
+        ] {
+            assert!(!has_valid_checksum(vector), "{}", vector);
+        }
+    }
+
+    #[test]
+    fn round_trips_wallet_ids() {
+        let address = encode_with_hrp(DEFAULT_ADDRESS_HRP, WALLET_ID).unwrap();
+        assert!(address.starts_with("cw1"));
+        assert_eq!(decode(&address), Ok(WALLET_ID.to_string()));
+        assert_eq!(decode(&address.to_uppercase()), Ok(WALLET_ID.to_string()));
+        assert_eq!(decode(&WALLET_ID.to_uppercase()), Ok(WALLET_ID.to_string()));
+        assert_eq!(encode_with_hrp(DEFAULT_ADDRESS_HRP, "ZAKAT_POOL"), None);
+    }
+
+    #[test]
+    fn rejects_single_character_typos() {
+        let address = encode_with_hrp(DEFAULT_ADDRESS_HRP, WALLET_ID).unwrap();
+        for i in DEFAULT_ADDRESS_HRP.len() + 1..address.len() {
+            let mut typo = address.clone().into_bytes();
+            typo[i] = if typo[i] == b'q' { b'p' } else { b'q' };
+            let typo = String::from_utf8(typo).unwrap();
+            assert_eq!(decode(&typo), Err("checksum mismatch".to_string()), "{}", typo);
+        }
+    }
+
+    #[test]
+    fn rejects_mixed_case() {
+        let address = encode_with_hrp(DEFAULT_ADDRESS_HRP, WALLET_ID).unwrap();
+        let mixed = format!("{}{}", &address[..10], address[10..].to_uppercase());
+        assert_eq!(decode(&mixed), Err("mixed case".to_string()));
+    }
+
+    #[test]
+    fn rejects_other_network_prefix() {
+        let address = encode_with_hrp("tb", WALLET_ID).unwrap();
+        assert!(has_valid_checksum(&address));
+        assert!(decode(&address).unwrap_err().starts_with("prefix 'tb'"));
+    }
+}
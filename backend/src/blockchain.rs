@@ -1,40 +1,221 @@
-use crate::models::{Block, Transaction, BlockchainState};
-use crate::crypto::hash_sha256;
-use chrono::Utc;
+use crate::models::{Block, BlockHeader, Transaction, TransactionStatus, BlockchainState, MerkleStep, SiblingPosition, UTXO};
+use crate::crypto::{hash_sha256, generate_wallet_id, transaction_payload, verify_signature};
+use crate::error::ValidationError;
+use chrono::{DateTime, Duration, Timelike, Utc};
 use serde_json::to_string;
+use std::collections::{HashMap, HashSet};
+use std::sync::OnceLock;
+use uuid::Uuid;
 
 pub const DIFFICULTY_PREFIX: &str = "00000";
+pub const MAX_BLOCK_SIZE: usize = 1_000_000;
+pub const MEDIAN_TIME_SPAN: usize = 11;
+pub const MAX_FUTURE_BLOCK_TIME_SECS: i64 = 2 * 60 * 60;
+pub const DEFAULT_GENESIS_TIMESTAMP: &str = "2024-01-01T00:00:00Z";
+pub const LEGACY_BLOCK_VERSION: u32 = 1;
+pub const CURRENT_BLOCK_VERSION: u32 = 4;
+// First version whose hash covers only the binary header
+pub const HEADER_HASH_VERSION: u32 = 3;
+// First version in which every transaction must carry the chain ID and a sender nonce
+pub const REPLAY_PROTECTION_VERSION: u32 = 4;
+// Signed into every transaction so it is only valid on this network
+pub const DEFAULT_CHAIN_ID: &str = "crypto-wallet";
+// Sender of the transaction paying the block reward. No new coins are issued, so the reward is
+// at most the fees of the block's other transactions.
+pub const COINBASE_WALLET_ID: &str = "COINBASE";
+pub const MAX_TRANSACTION_OUTPUTS: usize = 500;
+
+// Nodes created before the genesis block was derived from GENESIS_TIMESTAMP stored one with this
+// fixed hash and a wall-clock timestamp. Such a database keeps its genesis block.
+static LEGACY_GENESIS: OnceLock<Block> = OnceLock::new();
+
+const MERKLE_LEAF_PREFIX: u8 = 0x00;
+const MERKLE_NODE_PREFIX: u8 = 0x01;
+
+// Tolerance for comparing f64 amounts
+const AMOUNT_EPSILON: f64 = 1e-9;
+
+// Spendable outputs and recent block times as of a chain tip
+#[derive(Debug, Clone, Default)]
+pub struct ChainState {
+    pub tip_hash: String,
+    pub height: u64,
+    pub version: u32,
+    pub unspent: HashMap<String, UTXO>,
+    pub recent_timestamps: Vec<DateTime<Utc>>,
+    // Highest nonce each wallet has used on this chain
+    pub nonces: HashMap<String, u64>,
+}
+
+impl ChainState {
+    // Start from outputs that exist before any block, e.g. allocations not created by a transaction
+    pub fn new(unspent: HashMap<String, UTXO>) -> Self {
+        ChainState {
+            unspent,
+            ..Default::default()
+        }
+    }
+
+    pub fn median_time_past(&self) -> Option<DateTime<Utc>> {
+        if self.recent_timestamps.is_empty() {
+            return None;
+        }
+        let mut times = self.recent_timestamps.clone();
+        times.sort();
+        Some(times[times.len() / 2])
+    }
+
+    // Spend the block's inputs and add its outputs, looking amounts up in the known UTXO documents
+    pub fn apply_block(&mut self, block: &Block, outputs: &HashMap<String, UTXO>) {
+        for tx in &block.transactions {
+            self.apply_transaction(tx, outputs);
+        }
+
+        self.tip_hash = block.hash.clone();
+        self.height = block.index;
+        self.version = block.version;
+        self.recent_timestamps.push(block.timestamp);
+        if self.recent_timestamps.len() > MEDIAN_TIME_SPAN {
+            self.recent_timestamps.remove(0);
+        }
+    }
+
+    fn apply_transaction(&mut self, tx: &Transaction, outputs: &HashMap<String, UTXO>) {
+        for input in &tx.input_utxos {
+            self.unspent.remove(input);
+        }
+        for output_id in tx.output_ids() {
+            if let Some(output) = outputs.get(&output_id) {
+                self.unspent.insert(output_id, output.clone());
+            }
+        }
+        if let Some(nonce) = tx.nonce {
+            self.nonces.insert(tx.sender_wallet_id.clone(), nonce);
+        }
+    }
+}
 
 pub struct Blockchain;
 
 impl Blockchain {
-    pub fn calculate_merkle_root(transactions: &[Transaction]) -> String {
-        if transactions.is_empty() {
-            return hash_sha256(b"");
+    // Version 1 hashes hex strings as text and duplicates the last node of odd levels. Version 2
+    // hashes raw bytes with a 0x00 prefix for leaves and 0x01 for inner nodes, and carries an odd
+    // node up unchanged, so no two different transaction lists share a root. Either way every
+    // hash must be a hex SHA-256 digest.
+    fn merkle_hash_bytes(hash: &str) -> Option<[u8; 32]> {
+        hex::decode(hash).ok()?.try_into().ok()
+    }
+
+    fn merkle_leaf(tx_hash: &str, version: u32) -> Option<String> {
+        let bytes = Self::merkle_hash_bytes(tx_hash)?;
+        if version == LEGACY_BLOCK_VERSION {
+            return Some(hash_sha256(tx_hash.as_bytes()));
         }
+        let mut data = vec![MERKLE_LEAF_PREFIX];
+        data.extend_from_slice(&bytes);
+        Some(hash_sha256(&data))
+    }
+
+    fn merkle_parent(left: &str, right: &str, version: u32) -> Option<String> {
+        let left_bytes = Self::merkle_hash_bytes(left)?;
+        let right_bytes = Self::merkle_hash_bytes(right)?;
+        if version == LEGACY_BLOCK_VERSION {
+            return Some(hash_sha256(format!("{}{}", left, right).as_bytes()));
+        }
+        let mut data = vec![MERKLE_NODE_PREFIX];
+        data.extend_from_slice(&left_bytes);
+        data.extend_from_slice(&right_bytes);
+        Some(hash_sha256(&data))
+    }
 
-        let mut hashes: Vec<String> = transactions
+    // Every level of the tree from the leaves up; legacy levels include the duplicated padding node.
+    // None if a transaction hash is not a hex SHA-256 digest.
+    fn merkle_levels(transactions: &[Transaction], version: u32) -> Option<Vec<Vec<String>>> {
+        let mut level = transactions
             .iter()
-            .map(|tx| hash_sha256(tx.transaction_hash.as_bytes()))
-            .collect();
+            .map(|tx| Self::merkle_leaf(&tx.transaction_hash, version))
+            .collect::<Option<Vec<String>>>()?;
 
-        while hashes.len() > 1 {
-            if hashes.len() % 2 != 0 {
-                hashes.push(hashes[hashes.len() - 1].clone());
+        let mut levels = Vec::new();
+        while level.len() > 1 {
+            if version == LEGACY_BLOCK_VERSION && level.len() % 2 != 0 {
+                level.push(level[level.len() - 1].clone());
             }
 
-            let mut new_hashes = Vec::new();
-            for i in (0..hashes.len()).step_by(2) {
-                let combined = format!("{}{}", hashes[i], hashes[i + 1]);
-                new_hashes.push(hash_sha256(combined.as_bytes()));
-            }
-            hashes = new_hashes;
+            let next = level
+                .chunks(2)
+                .map(|pair| match pair {
+                    [left, right] => Self::merkle_parent(left, right, version),
+                    _ => Some(pair[0].clone()),
+                })
+                .collect::<Option<Vec<String>>>()?;
+            levels.push(level);
+            level = next;
         }
+        levels.push(level);
+        Some(levels)
+    }
 
-        hashes[0].clone()
+    pub fn calculate_merkle_root(transactions: &[Transaction], version: u32) -> Result<String, ValidationError> {
+        if transactions.is_empty() {
+            return Ok(hash_sha256(b""));
+        }
+
+        let levels = Self::merkle_levels(transactions, version).ok_or_else(|| {
+            let invalid = transactions
+                .iter()
+                .find(|tx| Self::merkle_hash_bytes(&tx.transaction_hash).is_none())
+                .map(|tx| tx.transaction_hash.clone())
+                .unwrap_or_default();
+            ValidationError::InvalidTransactionHash(invalid)
+        })?;
+        Ok(levels.last().map(|root| root[0].clone()).unwrap_or_default())
+    }
+
+    // A list with a repeated transaction can reproduce the legacy root of a shorter list
+    // (CVE-2012-2459), so such lists are rejected outright
+    pub fn merkle_tree_mutated(transactions: &[Transaction]) -> bool {
+        let mut seen = HashSet::new();
+        transactions.iter().any(|tx| !seen.insert(&tx.transaction_hash))
+    }
+
+    // Sibling hashes from the transaction's leaf up to the root
+    pub fn merkle_branch(transactions: &[Transaction], tx_hash: &str, version: u32) -> Option<Vec<MerkleStep>> {
+        let mut index = transactions.iter().position(|tx| tx.transaction_hash == tx_hash)?;
+
+        let levels = Self::merkle_levels(transactions, version)?;
+        let mut branch = Vec::new();
+        for level in &levels[..levels.len() - 1] {
+            // An odd node carried up unchanged in version 2 has no sibling at this level
+            let step = if index % 2 == 0 {
+                level.get(index + 1).map(|hash| MerkleStep { hash: hash.clone(), position: SiblingPosition::Right })
+            } else {
+                Some(MerkleStep { hash: level[index - 1].clone(), position: SiblingPosition::Left })
+            };
+            branch.extend(step);
+            index /= 2;
+        }
+        Some(branch)
+    }
+
+    // Light-client check that a transaction hash is committed to by a block's merkle root
+    // Malformed hashes anywhere in the proof make it invalid
+    pub fn verify_merkle_proof(tx_hash: &str, branch: &[MerkleStep], merkle_root: &str, version: u32) -> bool {
+        let mut current = Self::merkle_leaf(tx_hash, version);
+        for step in branch {
+            current = current.and_then(|current| match step.position {
+                SiblingPosition::Left => Self::merkle_parent(&step.hash, &current, version),
+                SiblingPosition::Right => Self::merkle_parent(&current, &step.hash, version),
+            });
+        }
+        current.is_some_and(|root| root == merkle_root)
     }
 
     pub fn calculate_block_hash(block: &Block) -> String {
+        if block.version >= HEADER_HASH_VERSION {
+            return Self::header_hash(&Self::block_header(block));
+        }
+
         let block_data = format!(
             "{}{}{}{}{}{}{}",
             block.index,
@@ -48,70 +229,981 @@ impl Blockchain {
         hash_sha256(block_data.as_bytes())
     }
 
+    // Fixed 88-byte layout, all integers little-endian:
+    // version u32 | previous hash 32 | merkle root 32 | timestamp i64 seconds | bits u32 | nonce u64
+    pub fn serialize_header(header: &BlockHeader) -> Option<Vec<u8>> {
+        let previous_hash: [u8; 32] = hex::decode(&header.previous_hash).ok()?.try_into().ok()?;
+        let merkle_root: [u8; 32] = hex::decode(&header.merkle_root).ok()?.try_into().ok()?;
+
+        let mut data = Vec::with_capacity(88);
+        data.extend_from_slice(&header.version.to_le_bytes());
+        data.extend_from_slice(&previous_hash);
+        data.extend_from_slice(&merkle_root);
+        data.extend_from_slice(&header.timestamp.timestamp().to_le_bytes());
+        data.extend_from_slice(&header.difficulty.to_le_bytes());
+        data.extend_from_slice(&header.nonce.to_le_bytes());
+        Some(data)
+    }
+
+    // Transactions are committed only through the merkle root, so the hash can be checked from the header alone
+    pub fn header_hash(header: &BlockHeader) -> String {
+        Self::serialize_header(header)
+            .map(|data| hash_sha256(&data))
+            .unwrap_or_default()
+    }
+
     pub fn mine_block(
         index: u64,
         transactions: Vec<Transaction>,
         previous_hash: String,
         difficulty: u32,
-    ) -> Block {
-        let merkle_root = Self::calculate_merkle_root(&transactions);
+    ) -> Result<Block, ValidationError> {
+        let merkle_root = Self::calculate_merkle_root(&transactions, CURRENT_BLOCK_VERSION)?;
         let mut nonce = 0u64;
 
         loop {
+            // The header stores whole seconds, so keep the block timestamp identical to what is hashed
+            let now = Utc::now();
+            let timestamp = now.with_nanosecond(0).unwrap_or(now);
             let mut block = Block {
                 id: None,
+                version: CURRENT_BLOCK_VERSION,
                 index,
-                timestamp: Utc::now(),
+                timestamp,
                 transactions: transactions.clone(),
                 previous_hash: previous_hash.clone(),
                 nonce,
                 hash: String::new(),
                 merkle_root: merkle_root.clone(),
                 difficulty,
+                main_chain: true,
+                chain_work: 0,
             };
 
             block.hash = Self::calculate_block_hash(&block);
 
             if block.hash.starts_with(DIFFICULTY_PREFIX) {
-                return block;
+                return Ok(block);
             }
 
             nonce += 1;
         }
     }
 
-    pub fn validate_block(block: &Block, previous_hash: &str, difficulty: u32) -> bool {
+    // Checks a transaction against the spendable outputs and returns its fee. `block_version` is the
+    // version of the block it goes into, or CURRENT_BLOCK_VERSION for the mempool.
+    pub fn validate_transaction(
+        tx: &Transaction,
+        unspent: &HashMap<String, UTXO>,
+        outputs: &HashMap<String, UTXO>,
+        block_version: u32,
+    ) -> Result<f64, ValidationError> {
+        let tx_hash = tx.transaction_hash.clone();
+
+        // Blocks commit to their transactions through these hashes, and the database keys records
+        // by them, so from HEADER_HASH_VERSION a hash must be the hash of the signed contents
+        if block_version >= HEADER_HASH_VERSION && tx_hash != hash_sha256(Self::signing_payload(tx).as_bytes()) {
+            return Err(ValidationError::TransactionHashMismatch(tx_hash));
+        }
+
+        if !verify_signature(&Self::signing_payload(tx), &tx.digital_signature, &tx.sender_public_key)
+            || generate_wallet_id(&tx.sender_public_key) != tx.sender_wallet_id
+        {
+            return Err(ValidationError::InvalidSignature(tx_hash));
+        }
+
+        if tx.input_utxos.is_empty() {
+            return Err(ValidationError::NoInputs(tx_hash));
+        }
+
+        let mut seen = HashSet::new();
+        let mut input_total = 0.0;
+        for input in &tx.input_utxos {
+            if !seen.insert(input) {
+                return Err(ValidationError::DoubleSpend { tx: tx_hash, utxo: input.clone() });
+            }
+            let utxo = unspent
+                .get(input)
+                .ok_or_else(|| ValidationError::UnknownInput { tx: tx_hash.clone(), utxo: input.clone() })?;
+            if utxo.wallet_id != tx.sender_wallet_id {
+                return Err(ValidationError::InputNotOwned { tx: tx_hash, utxo: input.clone() });
+            }
+            input_total += utxo.amount;
+        }
+
+        let output_total = if tx.outputs.is_empty() {
+            Self::legacy_output_total(tx, outputs)?
+        } else {
+            Self::listed_output_total(tx, outputs)?
+        };
+
+        if input_total + AMOUNT_EPSILON < output_total {
+            return Err(ValidationError::InsufficientInputs {
+                tx: tx_hash,
+                inputs: input_total,
+                outputs: output_total,
+            });
+        }
+
+        let fee = input_total - output_total;
+        if tx.fee < 0.0 || (fee - tx.fee).abs() > AMOUNT_EPSILON {
+            return Err(ValidationError::FeeMismatch {
+                tx: tx_hash,
+                declared: tx.fee,
+                actual: fee,
+            });
+        }
+
+        Ok(fee)
+    }
+
+    pub fn chain_id() -> String {
+        std::env::var("CHAIN_ID").unwrap_or_else(|_| DEFAULT_CHAIN_ID.to_string())
+    }
+
+    // A transaction must name this chain and use a nonce above any its sender used before, so a
+    // signed transaction cannot be replayed on another network or again on this one. Blocks
+    // before REPLAY_PROTECTION_VERSION may also hold transactions with neither.
+    pub fn check_replay_protection(
+        tx: &Transaction,
+        nonces: &HashMap<String, u64>,
+        block_version: u32,
+    ) -> Result<(), ValidationError> {
+        let tx_hash = tx.transaction_hash.clone();
+        match (&tx.chain_id, tx.nonce) {
+            (None, None) if block_version < REPLAY_PROTECTION_VERSION => Ok(()),
+            (Some(chain_id), Some(nonce)) => {
+                if *chain_id != Self::chain_id() {
+                    return Err(ValidationError::WrongChain { tx: tx_hash, chain_id: chain_id.clone() });
+                }
+                // Replay-protected transactions sign the multi-output payload; the legacy
+                // single-recipient payload is only valid for older transactions
+                if tx.outputs.is_empty() {
+                    return Err(ValidationError::MissingOutputs(tx_hash));
+                }
+                if let Some(&last) = nonces.get(&tx.sender_wallet_id) {
+                    if nonce <= last {
+                        return Err(ValidationError::NonceReused { tx: tx_hash, nonce, last });
+                    }
+                }
+                Ok(())
+            }
+            _ => Err(ValidationError::MissingReplayProtection(tx_hash)),
+        }
+    }
+
+    // What the sender signs; the transaction hash is the hash of the same payload. Replay-protected
+    // transactions prefix it with the chain ID and nonce.
+    pub fn signing_payload(tx: &Transaction) -> String {
+        let payload = Self::unprotected_payload(tx);
+        match (&tx.chain_id, tx.nonce) {
+            (Some(chain_id), Some(nonce)) => format!("{}:{}:{}", chain_id, nonce, payload),
+            _ => payload,
+        }
+    }
+
+    fn unprotected_payload(tx: &Transaction) -> String {
+        if tx.outputs.is_empty() {
+            return transaction_payload(
+                &tx.sender_wallet_id,
+                &tx.recipient_wallet_id,
+                tx.amount,
+                tx.fee,
+                tx.timestamp.timestamp(),
+            );
+        }
+
+        // Every field is labelled and delimited; the free-text note comes last so it cannot shift the others
+        let outputs: Vec<String> = tx
+            .outputs
+            .iter()
+            .map(|o| format!("{}:{}:{}", o.utxo_id, o.wallet_id, o.amount))
+            .collect();
+        format!(
+            "from={}|in={}|out={}|fee={}|time={}|note={}",
+            tx.sender_wallet_id,
+            tx.input_utxos.join(","),
+            outputs.join(";"),
+            tx.fee,
+            tx.timestamp.timestamp(),
+            tx.note.as_deref().unwrap_or("")
+        )
+    }
+
+    // Single payment plus optional change, with amounts held only in the UTXO documents
+    fn legacy_output_total(tx: &Transaction, outputs: &HashMap<String, UTXO>) -> Result<f64, ValidationError> {
+        let tx_hash = tx.transaction_hash.clone();
+
+        // The payment output must pay the recipient exactly the transaction amount
+        let output = outputs
+            .get(&tx.output_utxo)
+            .ok_or_else(|| ValidationError::InvalidOutput(tx_hash.clone()))?;
+        if tx.amount <= 0.0
+            || output.wallet_id != tx.recipient_wallet_id
+            || (output.amount - tx.amount).abs() > AMOUNT_EPSILON
+        {
+            return Err(ValidationError::InvalidOutput(tx_hash));
+        }
+
+        let mut output_total = output.amount;
+        if let Some(change_id) = &tx.change_utxo {
+            let change = outputs
+                .get(change_id)
+                .ok_or_else(|| ValidationError::InvalidOutput(tx_hash.clone()))?;
+            if change.wallet_id != tx.sender_wallet_id || change.amount < 0.0 {
+                return Err(ValidationError::InvalidOutput(tx_hash));
+            }
+            output_total += change.amount;
+        }
+        Ok(output_total)
+    }
+
+    // Signed output list: each entry needs a matching UTXO document, and the summary fields
+    // (first recipient, amount excluding change) must agree with the list since they are not signed
+    fn listed_output_total(tx: &Transaction, outputs: &HashMap<String, UTXO>) -> Result<f64, ValidationError> {
+        let invalid = || ValidationError::InvalidOutput(tx.transaction_hash.clone());
+        if tx.outputs.len() > MAX_TRANSACTION_OUTPUTS {
+            return Err(invalid());
+        }
+
+        let mut seen = HashSet::new();
+        let mut output_total = 0.0;
+        let mut paid = 0.0;
+        for output in &tx.outputs {
+            let utxo = outputs.get(&output.utxo_id).ok_or_else(invalid)?;
+            if !seen.insert(&output.utxo_id)
+                || output.amount <= 0.0
+                || utxo.transaction_hash != tx.transaction_hash
+                || utxo.wallet_id != output.wallet_id
+                || (utxo.amount - output.amount).abs() > AMOUNT_EPSILON
+            {
+                return Err(invalid());
+            }
+            output_total += output.amount;
+            if tx.change_utxo.as_ref() != Some(&output.utxo_id) {
+                paid += output.amount;
+            }
+        }
+
+        let first = &tx.outputs[0];
+        let change_ok = match &tx.change_utxo {
+            Some(id) => tx.outputs.iter().any(|o| &o.utxo_id == id && o.wallet_id == tx.sender_wallet_id),
+            None => true,
+        };
+        if first.utxo_id != tx.output_utxo
+            || first.wallet_id != tx.recipient_wallet_id
+            || tx.change_utxo.as_ref() == Some(&first.utxo_id)
+            || !change_ok
+            || (paid - tx.amount).abs() > AMOUNT_EPSILON
+        {
+            return Err(invalid());
+        }
+        Ok(output_total)
+    }
+
+    pub fn is_coinbase(tx: &Transaction) -> bool {
+        tx.sender_wallet_id == COINBASE_WALLET_ID && tx.input_utxos.is_empty()
+    }
+
+    // The hash ties the coinbase to its parent block, so competing blocks never share one
+    fn coinbase_hash(index: u64, previous_hash: &str, miner_wallet_id: &str) -> String {
+        hash_sha256(format!("coinbase{}{}{}", previous_hash, index, miner_wallet_id).as_bytes())
+    }
+
+    // Reward transaction paying the collected fees to the miner, together with its output
+    pub fn create_coinbase(index: u64, previous_hash: &str, miner_wallet_id: &str, reward: f64) -> (Transaction, UTXO) {
+        let transaction_hash = Self::coinbase_hash(index, previous_hash, miner_wallet_id);
+        let output = UTXO {
+            id: None,
+            utxo_id: Uuid::new_v4().to_string(),
+            wallet_id: miner_wallet_id.to_string(),
+            amount: reward,
+            status: "unspent".to_string(),
+            block_hash: "pending".to_string(),
+            transaction_hash: transaction_hash.clone(),
+            created_at: Utc::now(),
+        };
+        let transaction = Transaction {
+            id: None,
+            transaction_hash,
+            sender_wallet_id: COINBASE_WALLET_ID.to_string(),
+            recipient_wallet_id: miner_wallet_id.to_string(),
+            amount: reward,
+            fee: 0.0,
+            note: Some("Block reward".to_string()),
+            timestamp: Utc::now(),
+            sender_public_key: String::new(),
+            digital_signature: String::new(),
+            input_utxos: Vec::new(),
+            output_utxo: output.utxo_id.clone(),
+            change_utxo: None,
+            outputs: Vec::new(),
+            chain_id: None,
+            nonce: None,
+            status: TransactionStatus::Pending,
+            block_hash: None,
+        };
+        (transaction, output)
+    }
+
+    fn check_coinbase(
+        tx: &Transaction,
+        block: &Block,
+        fees: f64,
+        outputs: &HashMap<String, UTXO>,
+    ) -> Result<(), ValidationError> {
+        let invalid = || ValidationError::InvalidCoinbase(tx.transaction_hash.clone());
+        if tx.transaction_hash != Self::coinbase_hash(block.index, &block.previous_hash, &tx.recipient_wallet_id)
+            || tx.change_utxo.is_some()
+            || tx.amount <= 0.0
+            || tx.amount > fees + AMOUNT_EPSILON
+        {
+            return Err(invalid());
+        }
+        let output = outputs.get(&tx.output_utxo).ok_or_else(invalid)?;
+        if output.wallet_id != tx.recipient_wallet_id || (output.amount - tx.amount).abs() > AMOUNT_EPSILON {
+            return Err(invalid());
+        }
+        Ok(())
+    }
+
+    // Expected number of hashes to find a block: each required leading hex zero is 16x the work
+    pub fn block_work(block: &Block) -> u64 {
+        16u64.saturating_pow(block.difficulty)
+    }
+
+    pub fn block_header(block: &Block) -> BlockHeader {
+        BlockHeader {
+            version: block.version,
+            index: block.index,
+            timestamp: block.timestamp,
+            previous_hash: block.previous_hash.clone(),
+            nonce: block.nonce,
+            hash: block.hash.clone(),
+            merkle_root: block.merkle_root.clone(),
+            difficulty: block.difficulty,
+        }
+    }
+
+    // Header chain checks used before bodies are downloaded. Headers from before the binary layout
+    // cannot be rehashed on their own, so block download checks them against their bodies.
+    pub fn check_header(header: &BlockHeader, previous: &BlockHeader) -> Result<(), ValidationError> {
+        if header.version < previous.version || header.version > CURRENT_BLOCK_VERSION {
+            return Err(ValidationError::UnsupportedVersion(header.version));
+        }
+        if header.version >= HEADER_HASH_VERSION && Self::header_hash(header) != header.hash {
+            return Err(ValidationError::HashMismatch);
+        }
+        if header.previous_hash != previous.hash || header.index != previous.index + 1 {
+            return Err(ValidationError::PreviousHashMismatch {
+                expected: previous.hash.clone(),
+                found: header.previous_hash.clone(),
+            });
+        }
+        if !header.hash.starts_with(DIFFICULTY_PREFIX) {
+            return Err(ValidationError::InsufficientWork);
+        }
+        if header.difficulty != DIFFICULTY_PREFIX.len() as u32 {
+            return Err(ValidationError::UnexpectedDifficulty(header.difficulty));
+        }
+        if header.timestamp > Utc::now() + Duration::seconds(MAX_FUTURE_BLOCK_TIME_SECS) {
+            return Err(ValidationError::TimestampTooFarInFuture);
+        }
+        Ok(())
+    }
+
+    // Checks that need nothing but the block itself
+    pub fn check_block(block: &Block) -> Result<(), ValidationError> {
         // Check if hash matches difficulty
         if !block.hash.starts_with(DIFFICULTY_PREFIX) {
-            return false;
+            return Err(ValidationError::InsufficientWork);
+        }
+        if block.difficulty != DIFFICULTY_PREFIX.len() as u32 {
+            return Err(ValidationError::UnexpectedDifficulty(block.difficulty));
         }
 
-        // Check if previous hash matches
-        if block.previous_hash != previous_hash {
-            return false;
+        if block.version < LEGACY_BLOCK_VERSION || block.version > CURRENT_BLOCK_VERSION {
+            return Err(ValidationError::UnsupportedVersion(block.version));
         }
 
         // Verify merkle root
-        if block.merkle_root != Self::calculate_merkle_root(&block.transactions) {
-            return false;
+        if Self::merkle_tree_mutated(&block.transactions) {
+            return Err(ValidationError::MutatedMerkleTree);
+        }
+        if block.merkle_root != Self::calculate_merkle_root(&block.transactions, block.version)? {
+            return Err(ValidationError::MerkleRootMismatch);
         }
 
         // Verify calculated hash
-        let calculated_hash = Self::calculate_block_hash(block);
-        calculated_hash == block.hash
+        if Self::calculate_block_hash(block) != block.hash {
+            return Err(ValidationError::HashMismatch);
+        }
+
+        let size = to_string(&block.transactions).map(|s| s.len()).unwrap_or_default();
+        if size > MAX_BLOCK_SIZE {
+            return Err(ValidationError::BlockTooLarge { size, max: MAX_BLOCK_SIZE });
+        }
+
+        Ok(())
     }
 
+    pub fn validate_block(
+        block: &Block,
+        state: &ChainState,
+        outputs: &HashMap<String, UTXO>,
+    ) -> Result<(), ValidationError> {
+        Self::check_block(block)?;
+        Self::check_block_in_context(block, state, outputs)
+    }
+
+    // Rules that depend on the chain the block extends: linkage, timestamps and its transactions
+    fn check_block_in_context(
+        block: &Block,
+        state: &ChainState,
+        outputs: &HashMap<String, UTXO>,
+    ) -> Result<(), ValidationError> {
+        // Once a newer block version is in the chain, older versions are no longer accepted
+        if block.version < state.version {
+            return Err(ValidationError::UnsupportedVersion(block.version));
+        }
+
+        // Check if previous hash matches
+        if block.previous_hash != state.tip_hash {
+            return Err(ValidationError::PreviousHashMismatch {
+                expected: state.tip_hash.clone(),
+                found: block.previous_hash.clone(),
+            });
+        }
+
+        // Timestamp must be after the median of recent blocks and not too far ahead of our clock
+        if let Some(median) = state.median_time_past() {
+            if block.timestamp <= median {
+                return Err(ValidationError::TimestampTooEarly);
+            }
+        }
+        if block.timestamp > Utc::now() + Duration::seconds(MAX_FUTURE_BLOCK_TIME_SECS) {
+            return Err(ValidationError::TimestampTooFarInFuture);
+        }
+
+        // Transactions may spend outputs created earlier in the same block, but never twice
+        let mut pending = state.clone();
+        let mut seen_txs = HashSet::new();
+        let mut spent_in_block = HashSet::new();
+        let mut fees = 0.0;
+        for (i, tx) in block.transactions.iter().enumerate() {
+            if !seen_txs.insert(&tx.transaction_hash) {
+                return Err(ValidationError::DuplicateTransaction(tx.transaction_hash.clone()));
+            }
+            // Only the first transaction may be the coinbase; it is checked once the fees are known
+            if Self::is_coinbase(tx) {
+                if i != 0 {
+                    return Err(ValidationError::InvalidCoinbase(tx.transaction_hash.clone()));
+                }
+                continue;
+            }
+            for input in &tx.input_utxos {
+                if spent_in_block.contains(input) {
+                    return Err(ValidationError::DoubleSpend {
+                        tx: tx.transaction_hash.clone(),
+                        utxo: input.clone(),
+                    });
+                }
+            }
+
+            Self::check_replay_protection(tx, &pending.nonces, block.version)?;
+            fees += Self::validate_transaction(tx, &pending.unspent, outputs, block.version)?;
+
+            spent_in_block.extend(tx.input_utxos.iter().cloned());
+            pending.apply_transaction(tx, outputs);
+        }
+
+        if let Some(coinbase) = block.transactions.first().filter(|tx| Self::is_coinbase(tx)) {
+            Self::check_coinbase(coinbase, block, fees, outputs)?;
+        }
+
+        Ok(())
+    }
+
+    // Keeps the candidates that are valid in order on top of the given state, dropping the rest
+    pub fn select_valid_transactions(
+        candidates: Vec<Transaction>,
+        state: &ChainState,
+        outputs: &HashMap<String, UTXO>,
+    ) -> Vec<Transaction> {
+        let mut pending = state.clone();
+        let mut selected = Vec::new();
+        for tx in candidates {
+            let valid = Self::check_replay_protection(&tx, &pending.nonces, CURRENT_BLOCK_VERSION)
+                .and_then(|_| Self::validate_transaction(&tx, &pending.unspent, outputs, CURRENT_BLOCK_VERSION));
+            match valid {
+                Ok(_) => {
+                    pending.apply_transaction(&tx, outputs);
+                    selected.push(tx);
+                }
+                Err(e) => log::warn!("Skipping transaction {}: {}", tx.transaction_hash, e),
+            }
+        }
+        selected
+    }
+
+    // Genesis is fixed by configuration so every node derives the same block 0
     pub fn create_genesis_block() -> Block {
-        Block {
+        if let Some(legacy) = LEGACY_GENESIS.get() {
+            return legacy.clone();
+        }
+        let timestamp = std::env::var("GENESIS_TIMESTAMP")
+            .ok()
+            .and_then(|t| DateTime::parse_from_rfc3339(&t).ok())
+            .map(|t| t.with_timezone(&Utc))
+            .unwrap_or_else(|| {
+                DateTime::parse_from_rfc3339(DEFAULT_GENESIS_TIMESTAMP)
+                    .expect("default genesis timestamp is valid")
+                    .with_timezone(&Utc)
+            });
+
+        let mut block = Block {
             id: None,
+            version: LEGACY_BLOCK_VERSION,
             index: 0,
-            timestamp: Utc::now(),
+            timestamp,
             transactions: Vec::new(),
             previous_hash: "0".to_string(),
             nonce: 0,
-            hash: hash_sha256(b"genesis"),
+            hash: String::new(),
+            // Root of an empty transaction list
             merkle_root: hash_sha256(b""),
-            difficulty: 1,
+            difficulty: DIFFICULTY_PREFIX.len() as u32,
+            main_chain: true,
+            chain_work: 0,
+        };
+        block.hash = Self::calculate_block_hash(&block);
+        block.chain_work = Self::block_work(&block);
+        block
+    }
+
+    pub fn is_valid_genesis(block: &Block) -> bool {
+        let expected = Self::create_genesis_block();
+        block.index == 0
+            && block.hash == expected.hash
+            && (Self::calculate_block_hash(block) == block.hash || LEGACY_GENESIS.get().is_some())
+    }
+
+    // The genesis block written by nodes predating the configured genesis: empty, and hashed
+    // from a constant rather than its contents
+    pub fn is_legacy_genesis(block: &Block) -> bool {
+        block.index == 0
+            && block.previous_hash == "0"
+            && block.transactions.is_empty()
+            && block.hash == hash_sha256(b"genesis")
+    }
+
+    // Makes a stored legacy genesis block the genesis of this node for the rest of the process
+    pub fn adopt_legacy_genesis(block: &Block) {
+        let mut genesis = block.clone();
+        genesis.id = None;
+        let _ = LEGACY_GENESIS.set(genesis);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::{public_key_from_private, sign_transaction};
+    use crate::models::TxOutput;
+
+    fn transaction(transaction_hash: String) -> Transaction {
+        Transaction {
+            id: None,
+            transaction_hash,
+            sender_wallet_id: "sender".to_string(),
+            recipient_wallet_id: "recipient".to_string(),
+            amount: 1.0,
+            fee: 0.0,
+            note: None,
+            timestamp: Utc::now(),
+            sender_public_key: String::new(),
+            digital_signature: String::new(),
+            input_utxos: Vec::new(),
+            output_utxo: String::new(),
+            change_utxo: None,
+            outputs: Vec::new(),
+            chain_id: None,
+            nonce: None,
+            status: TransactionStatus::Confirmed,
+            block_hash: None,
+        }
+    }
+
+    fn transactions(count: usize) -> Vec<Transaction> {
+        (0..count).map(|i| transaction(hash_sha256(format!("tx-{}", i).as_bytes()))).collect()
+    }
+
+    fn parent(left: &str, right: &str, version: u32) -> String {
+        Blockchain::merkle_parent(left, right, version).unwrap()
+    }
+
+    fn leaf(tx: &Transaction, version: u32) -> String {
+        Blockchain::merkle_leaf(&tx.transaction_hash, version).unwrap()
+    }
+
+    #[test]
+    fn proofs_round_trip_for_every_transaction() {
+        for version in [LEGACY_BLOCK_VERSION, CURRENT_BLOCK_VERSION] {
+            for count in 1..=9 {
+                let txs = transactions(count);
+                let root = Blockchain::calculate_merkle_root(&txs, version).unwrap();
+                for tx in &txs {
+                    let branch = Blockchain::merkle_branch(&txs, &tx.transaction_hash, version).unwrap();
+                    assert!(
+                        Blockchain::verify_merkle_proof(&tx.transaction_hash, &branch, &root, version),
+                        "version {} with {} transactions",
+                        version,
+                        count
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn proof_fails_for_another_root_or_transaction() {
+        let txs = transactions(5);
+        let root = Blockchain::calculate_merkle_root(&txs, CURRENT_BLOCK_VERSION).unwrap();
+        let branch = Blockchain::merkle_branch(&txs, &txs[2].transaction_hash, CURRENT_BLOCK_VERSION).unwrap();
+
+        assert!(!Blockchain::verify_merkle_proof(&txs[3].transaction_hash, &branch, &root, CURRENT_BLOCK_VERSION));
+        let other_root = Blockchain::calculate_merkle_root(&txs[..4], CURRENT_BLOCK_VERSION).unwrap();
+        assert!(!Blockchain::verify_merkle_proof(&txs[2].transaction_hash, &branch, &other_root, CURRENT_BLOCK_VERSION));
+    }
+
+    #[test]
+    fn legacy_root_duplicates_the_odd_node() {
+        let txs = transactions(3);
+        let v = LEGACY_BLOCK_VERSION;
+        let (a, b, c) = (leaf(&txs[0], v), leaf(&txs[1], v), leaf(&txs[2], v));
+
+        let expected = parent(&parent(&a, &b, v), &parent(&c, &c, v), v);
+        assert_eq!(Blockchain::calculate_merkle_root(&txs, v).unwrap(), expected);
+        assert_eq!(a, hash_sha256(txs[0].transaction_hash.as_bytes()));
+    }
+
+    #[test]
+    fn current_root_carries_the_odd_node_up() {
+        let txs = transactions(3);
+        let v = CURRENT_BLOCK_VERSION;
+        let (a, b, c) = (leaf(&txs[0], v), leaf(&txs[1], v), leaf(&txs[2], v));
+
+        let expected = parent(&parent(&a, &b, v), &c, v);
+        assert_eq!(Blockchain::calculate_merkle_root(&txs, v).unwrap(), expected);
+        // Repeating the last transaction no longer reproduces the root
+        let mut padded = txs.clone();
+        padded.push(txs[2].clone());
+        assert_ne!(Blockchain::calculate_merkle_root(&padded, v).unwrap(), expected);
+    }
+
+    #[test]
+    fn legacy_and_current_roots_differ() {
+        for count in 1..=4 {
+            let txs = transactions(count);
+            assert_ne!(
+                Blockchain::calculate_merkle_root(&txs, LEGACY_BLOCK_VERSION).unwrap(),
+                Blockchain::calculate_merkle_root(&txs, CURRENT_BLOCK_VERSION).unwrap()
+            );
         }
+        assert_eq!(
+            Blockchain::calculate_merkle_root(&[], LEGACY_BLOCK_VERSION).unwrap(),
+            Blockchain::calculate_merkle_root(&[], CURRENT_BLOCK_VERSION).unwrap()
+        );
+    }
+
+    #[test]
+    fn malformed_hashes_are_rejected() {
+        let mut txs = transactions(2);
+        txs[1].transaction_hash = "not-hex".to_string();
+        assert!(matches!(
+            Blockchain::calculate_merkle_root(&txs, CURRENT_BLOCK_VERSION),
+            Err(ValidationError::InvalidTransactionHash(hash)) if hash == "not-hex"
+        ));
+        txs[1].transaction_hash = "abcd".to_string();
+        assert!(Blockchain::calculate_merkle_root(&txs, LEGACY_BLOCK_VERSION).is_err());
+
+        let txs = transactions(2);
+        let root = Blockchain::calculate_merkle_root(&txs, CURRENT_BLOCK_VERSION).unwrap();
+        let mut branch = Blockchain::merkle_branch(&txs, &txs[0].transaction_hash, CURRENT_BLOCK_VERSION).unwrap();
+        branch[0].hash.truncate(62);
+        assert!(!Blockchain::verify_merkle_proof(&txs[0].transaction_hash, &branch, &root, CURRENT_BLOCK_VERSION));
+        assert!(!Blockchain::verify_merkle_proof("zz", &[], &root, CURRENT_BLOCK_VERSION));
+    }
+
+    struct Wallet {
+        private_key: String,
+        public_key: String,
+        wallet_id: String,
+    }
+
+    fn wallet(seed: u8) -> Wallet {
+        let private_key = hex::encode([seed; 32]);
+        let public_key = public_key_from_private(&private_key).unwrap();
+        Wallet {
+            wallet_id: generate_wallet_id(&public_key),
+            private_key,
+            public_key,
+        }
+    }
+
+    fn utxo(utxo_id: &str, wallet_id: &str, amount: f64, transaction_hash: &str) -> UTXO {
+        UTXO {
+            id: None,
+            utxo_id: utxo_id.to_string(),
+            wallet_id: wallet_id.to_string(),
+            amount,
+            status: "unspent".to_string(),
+            block_hash: "block".to_string(),
+            transaction_hash: transaction_hash.to_string(),
+            created_at: Utc::now(),
+        }
+    }
+
+    // Pays `amount` to `to` with any change back to the sender, signed by the sender
+    fn payment(from: &Wallet, inputs: &[&UTXO], to: &str, amount: f64, fee: f64, nonce: u64) -> Transaction {
+        let change = inputs.iter().map(|u| u.amount).sum::<f64>() - amount - fee;
+        let mut outputs = vec![TxOutput {
+            utxo_id: Uuid::new_v4().to_string(),
+            wallet_id: to.to_string(),
+            amount,
+        }];
+        if change > 0.0 {
+            outputs.push(TxOutput {
+                utxo_id: Uuid::new_v4().to_string(),
+                wallet_id: from.wallet_id.clone(),
+                amount: change,
+            });
+        }
+        let tx = Transaction {
+            sender_wallet_id: from.wallet_id.clone(),
+            recipient_wallet_id: to.to_string(),
+            amount,
+            fee,
+            sender_public_key: from.public_key.clone(),
+            input_utxos: inputs.iter().map(|u| u.utxo_id.clone()).collect(),
+            output_utxo: outputs[0].utxo_id.clone(),
+            change_utxo: outputs.get(1).map(|o| o.utxo_id.clone()),
+            outputs,
+            chain_id: Some(Blockchain::chain_id()),
+            nonce: Some(nonce),
+            status: TransactionStatus::Pending,
+            ..transaction(String::new())
+        };
+        signed(tx, from)
+    }
+
+    fn signed(mut tx: Transaction, from: &Wallet) -> Transaction {
+        let payload = Blockchain::signing_payload(&tx);
+        tx.transaction_hash = hash_sha256(payload.as_bytes());
+        tx.digital_signature = sign_transaction(&payload, &from.private_key);
+        tx
+    }
+
+    // The UTXO documents a node stores for the transactions' listed outputs
+    fn outputs_of(txs: &[&Transaction]) -> HashMap<String, UTXO> {
+        txs.iter()
+            .flat_map(|tx| {
+                tx.outputs
+                    .iter()
+                    .map(|o| (o.utxo_id.clone(), utxo(&o.utxo_id, &o.wallet_id, o.amount, &tx.transaction_hash)))
+            })
+            .collect()
+    }
+
+    fn state_with(unspent: &[&UTXO]) -> ChainState {
+        let mut state = ChainState::new(unspent.iter().map(|u| (u.utxo_id.clone(), (*u).clone())).collect());
+        state.tip_hash = hash_sha256(b"tip");
+        state.height = 1;
+        state.version = CURRENT_BLOCK_VERSION;
+        state
+    }
+
+    // Proof-of-work is covered by check_block; these blocks only go through the contextual checks
+    fn block_on(state: &ChainState, transactions: Vec<Transaction>) -> Block {
+        Block {
+            id: None,
+            version: CURRENT_BLOCK_VERSION,
+            index: state.height + 1,
+            timestamp: Utc::now(),
+            merkle_root: Blockchain::calculate_merkle_root(&transactions, CURRENT_BLOCK_VERSION).unwrap(),
+            transactions,
+            previous_hash: state.tip_hash.clone(),
+            nonce: 0,
+            hash: String::new(),
+            difficulty: DIFFICULTY_PREFIX.len() as u32,
+            main_chain: true,
+            chain_work: 0,
+        }
+    }
+
+    #[test]
+    fn signed_payment_is_valid_and_returns_its_fee() {
+        let alice = wallet(1);
+        let funds = utxo("funds", &alice.wallet_id, 10.0, "funding");
+        let tx = payment(&alice, &[&funds], &wallet(2).wallet_id, 4.0, 0.5, 1);
+
+        let state = state_with(&[&funds]);
+        let fee = Blockchain::validate_transaction(&tx, &state.unspent, &outputs_of(&[&tx]), CURRENT_BLOCK_VERSION);
+        assert!((fee.unwrap() - 0.5).abs() < AMOUNT_EPSILON);
+    }
+
+    #[test]
+    fn declared_fee_must_match_inputs_minus_outputs() {
+        let alice = wallet(1);
+        let funds = utxo("funds", &alice.wallet_id, 10.0, "funding");
+        let mut tx = payment(&alice, &[&funds], &wallet(2).wallet_id, 4.0, 0.5, 1);
+        tx.fee = 0.2;
+        let tx = signed(tx, &alice);
+
+        let state = state_with(&[&funds]);
+        let result = Blockchain::validate_transaction(&tx, &state.unspent, &outputs_of(&[&tx]), CURRENT_BLOCK_VERSION);
+        assert!(matches!(result, Err(ValidationError::FeeMismatch { declared, .. }) if declared == 0.2));
+    }
+
+    #[test]
+    fn altered_or_foreign_transactions_are_rejected() {
+        let alice = wallet(1);
+        let funds = utxo("funds", &alice.wallet_id, 10.0, "funding");
+        let state = state_with(&[&funds]);
+        let tx = payment(&alice, &[&funds], &wallet(2).wallet_id, 4.0, 0.5, 1);
+
+        // Changed after signing, keeping the original hash
+        let mut altered = tx.clone();
+        altered.note = Some("changed".to_string());
+        let result = Blockchain::validate_transaction(&altered, &state.unspent, &outputs_of(&[&tx]), CURRENT_BLOCK_VERSION);
+        assert!(matches!(result, Err(ValidationError::TransactionHashMismatch(_))));
+
+        // Signed by a key that does not own the sender wallet
+        let forged = signed(tx.clone(), &wallet(3));
+        let result = Blockchain::validate_transaction(&forged, &state.unspent, &outputs_of(&[&forged]), CURRENT_BLOCK_VERSION);
+        assert!(matches!(result, Err(ValidationError::InvalidSignature(_))));
+    }
+
+    #[test]
+    fn transaction_may_not_spend_an_input_twice() {
+        let alice = wallet(1);
+        let funds = utxo("funds", &alice.wallet_id, 10.0, "funding");
+        let tx = payment(&alice, &[&funds, &funds], &wallet(2).wallet_id, 15.0, 0.5, 1);
+
+        let state = state_with(&[&funds]);
+        let result = Blockchain::validate_transaction(&tx, &state.unspent, &outputs_of(&[&tx]), CURRENT_BLOCK_VERSION);
+        assert!(matches!(result, Err(ValidationError::DoubleSpend { utxo, .. }) if utxo == "funds"));
+    }
+
+    #[test]
+    fn block_may_not_spend_an_output_twice() {
+        let alice = wallet(1);
+        let funds = utxo("funds", &alice.wallet_id, 10.0, "funding");
+        let first = payment(&alice, &[&funds], &wallet(2).wallet_id, 4.0, 0.5, 1);
+        let second = payment(&alice, &[&funds], &wallet(3).wallet_id, 4.0, 0.5, 2);
+
+        let state = state_with(&[&funds]);
+        let block = block_on(&state, vec![first.clone(), second.clone()]);
+        let result = Blockchain::check_block_in_context(&block, &state, &outputs_of(&[&first, &second]));
+        assert!(matches!(result, Err(ValidationError::DoubleSpend { tx, .. }) if tx == second.transaction_hash));
+    }
+
+    #[test]
+    fn block_may_spend_outputs_created_earlier_in_it() {
+        let (alice, bob) = (wallet(1), wallet(2));
+        let funds = utxo("funds", &alice.wallet_id, 10.0, "funding");
+        let first = payment(&alice, &[&funds], &bob.wallet_id, 4.0, 0.5, 1);
+        let received = outputs_of(&[&first]).remove(&first.output_utxo).unwrap();
+        let second = payment(&bob, &[&received], &alice.wallet_id, 3.0, 0.5, 1);
+
+        let state = state_with(&[&funds]);
+        let block = block_on(&state, vec![first.clone(), second.clone()]);
+        assert!(Blockchain::check_block_in_context(&block, &state, &outputs_of(&[&first, &second])).is_ok());
+
+        // The other way round the second payment's input does not exist yet
+        let block = block_on(&state, vec![second.clone(), first.clone()]);
+        let result = Blockchain::check_block_in_context(&block, &state, &outputs_of(&[&first, &second]));
+        assert!(matches!(result, Err(ValidationError::UnknownInput { .. })));
+    }
+
+    #[test]
+    fn block_timestamp_must_follow_median_time_past() {
+        let mut state = state_with(&[]);
+        let now = Utc::now();
+        state.recent_timestamps = (0..MEDIAN_TIME_SPAN as i64).map(|i| now - Duration::minutes(i)).collect();
+
+        let mut block = block_on(&state, Vec::new());
+        block.timestamp = now - Duration::minutes(MEDIAN_TIME_SPAN as i64 / 2);
+        let result = Blockchain::check_block_in_context(&block, &state, &HashMap::new());
+        assert!(matches!(result, Err(ValidationError::TimestampTooEarly)));
+
+        block.timestamp = now + Duration::seconds(1);
+        assert!(Blockchain::check_block_in_context(&block, &state, &HashMap::new()).is_ok());
+    }
+
+    #[test]
+    fn block_timestamp_may_not_be_far_in_the_future() {
+        let state = state_with(&[]);
+        let mut block = block_on(&state, Vec::new());
+
+        block.timestamp = Utc::now() + Duration::seconds(MAX_FUTURE_BLOCK_TIME_SECS + 60);
+        let result = Blockchain::check_block_in_context(&block, &state, &HashMap::new());
+        assert!(matches!(result, Err(ValidationError::TimestampTooFarInFuture)));
+
+        block.timestamp = Utc::now() + Duration::seconds(MAX_FUTURE_BLOCK_TIME_SECS - 60);
+        assert!(Blockchain::check_block_in_context(&block, &state, &HashMap::new()).is_ok());
+    }
+
+    #[test]
+    fn nonces_may_not_be_reused() {
+        let alice = wallet(1);
+        let funds = utxo("funds", &alice.wallet_id, 10.0, "funding");
+        let other = utxo("other", &alice.wallet_id, 10.0, "funding");
+        let first = payment(&alice, &[&funds], &wallet(2).wallet_id, 4.0, 0.5, 5);
+        let second = payment(&alice, &[&other], &wallet(2).wallet_id, 4.0, 0.5, 5);
+
+        // Within one block
+        let state = state_with(&[&funds, &other]);
+        let block = block_on(&state, vec![first.clone(), second.clone()]);
+        let result = Blockchain::check_block_in_context(&block, &state, &outputs_of(&[&first, &second]));
+        assert!(matches!(result, Err(ValidationError::NonceReused { nonce: 5, last: 5, .. })));
+
+        // Against the chain, where a lower nonce is just as stale
+        let mut state = state_with(&[&other]);
+        state.nonces.insert(alice.wallet_id.clone(), 6);
+        let block = block_on(&state, vec![second.clone()]);
+        let result = Blockchain::check_block_in_context(&block, &state, &outputs_of(&[&second]));
+        assert!(matches!(result, Err(ValidationError::NonceReused { nonce: 5, last: 6, .. })));
+    }
+
+    #[test]
+    fn replay_protection_is_required_from_its_version() {
+        let alice = wallet(1);
+        let funds = utxo("funds", &alice.wallet_id, 10.0, "funding");
+        let tx = payment(&alice, &[&funds], &wallet(2).wallet_id, 4.0, 0.5, 1);
+        let nonces = HashMap::new();
+
+        let mut unprotected = tx.clone();
+        unprotected.chain_id = None;
+        unprotected.nonce = None;
+        assert!(Blockchain::check_replay_protection(&unprotected, &nonces, REPLAY_PROTECTION_VERSION - 1).is_ok());
+        assert!(matches!(
+            Blockchain::check_replay_protection(&unprotected, &nonces, REPLAY_PROTECTION_VERSION),
+            Err(ValidationError::MissingReplayProtection(_))
+        ));
+
+        let mut other_chain = tx.clone();
+        other_chain.chain_id = Some("other-chain".to_string());
+        assert!(matches!(
+            Blockchain::check_replay_protection(&other_chain, &nonces, CURRENT_BLOCK_VERSION),
+            Err(ValidationError::WrongChain { .. })
+        ));
+
+        let mut without_outputs = tx;
+        without_outputs.outputs.clear();
+        assert!(matches!(
+            Blockchain::check_replay_protection(&without_outputs, &nonces, CURRENT_BLOCK_VERSION),
+            Err(ValidationError::MissingOutputs(_))
+        ));
     }
 }
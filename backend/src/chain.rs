@@ -0,0 +1,299 @@
+use crate::blockchain::{Blockchain, ChainState};
+use crate::db::Database as DbOps;
+use crate::error::ApiError;
+use crate::mempool::Mempool;
+use crate::models::{Block, Transaction, TransactionStatus, UTXO};
+use mongodb::Database;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum BlockOutcome {
+    AlreadyKnown,
+    Extended,
+    SideChain,
+    Reorganized { disconnected: usize, connected: usize },
+}
+
+// State at the main-chain tip, advanced block by block so extending the chain does not replay it.
+// It is dropped whenever it may be stale and rebuilt from the database on the next block.
+static TIP_STATE: Mutex<Option<ChainState>> = Mutex::new(None);
+
+// Accepts blocks from miners and peers and keeps the main chain on the branch with most work
+pub struct ChainManager;
+
+impl ChainManager {
+    // `outputs` are the UTXOs the block's transactions create, as carried by a peer or a local miner.
+    // They are only stored once the block is accepted, so a rejected block never adds spendable funds.
+    pub async fn process_block(
+        db: &Database,
+        mempool: &Mempool,
+        mut block: Block,
+        outputs: Vec<UTXO>,
+    ) -> Result<BlockOutcome, ApiError> {
+        if DbOps::get_block_by_hash(db, &block.hash).await?.is_some() {
+            return Ok(BlockOutcome::AlreadyKnown);
+        }
+
+        Blockchain::check_block(&block)?;
+
+        let parent = DbOps::get_block_by_hash(db, &block.previous_hash)
+            .await?
+            .ok_or_else(|| ApiError::BadRequest(format!("Unknown parent block {}", block.previous_hash)))?;
+        if block.index != parent.index + 1 {
+            return Err(ApiError::BadRequest("Block index does not follow its parent".to_string()));
+        }
+
+        block.id = None;
+        block.chain_work = parent.chain_work + Blockchain::block_work(&block);
+
+        let outputs = Self::block_outputs(&block, outputs);
+        let tip = DbOps::get_chain_tip(db).await?;
+        if parent.hash == tip.hash {
+            let mut state = Self::tip_state(db, &tip.hash).await?;
+
+            // Validation only looks up the outputs of the block's own transactions
+            let tx_hashes: Vec<String> = block.transactions.iter().map(|tx| tx.transaction_hash.clone()).collect();
+            let mut utxos: HashMap<String, UTXO> = DbOps::get_utxos_by_transaction_hashes(db, &tx_hashes)
+                .await?
+                .into_iter()
+                .map(|u| (u.utxo_id.clone(), u))
+                .collect();
+            for output in &outputs {
+                utxos.entry(output.utxo_id.clone()).or_insert_with(|| output.clone());
+            }
+            if let Err(e) = Blockchain::validate_block(&block, &state, &utxos) {
+                Self::cache_tip_state(state);
+                return Err(e.into());
+            }
+
+            for output in &outputs {
+                DbOps::insert_utxo_if_missing(db, output).await?;
+            }
+            block.main_chain = true;
+            DbOps::append_block(db, &block).await?;
+            Self::connect_block(db, mempool, &block).await?;
+            state.apply_block(&block, &utxos);
+            Self::cache_tip_state(state);
+            return Ok(BlockOutcome::Extended);
+        }
+
+        // Competing branch: keep it on the side until it carries more work than the main chain. Its
+        // outputs are stored orphaned and only become spendable when the branch is connected.
+        for output in &outputs {
+            let mut output = output.clone();
+            output.status = "orphaned".to_string();
+            DbOps::insert_utxo_if_missing(db, &output).await?;
+        }
+        block.main_chain = false;
+        DbOps::create_block(db, &block).await?;
+        log::info!("Stored side-chain block {} at height {}", block.hash, block.index);
+
+        if block.chain_work > tip.chain_work {
+            return Self::reorganize(db, mempool, &block).await;
+        }
+        Ok(BlockOutcome::SideChain)
+    }
+
+    // Takes the cached state if it is still at `tip_hash`, otherwise replays the chain
+    async fn tip_state(db: &Database, tip_hash: &str) -> Result<ChainState, ApiError> {
+        let cached = TIP_STATE.lock().unwrap().take();
+        match cached {
+            Some(state) if state.tip_hash == tip_hash => Ok(state),
+            _ => Ok(DbOps::load_chain_state(db).await?.0),
+        }
+    }
+
+    fn cache_tip_state(state: ChainState) {
+        *TIP_STATE.lock().unwrap() = Some(state);
+    }
+
+    // The outputs belonging to the block's transactions, not yet tied to a block
+    fn block_outputs(block: &Block, outputs: Vec<UTXO>) -> Vec<UTXO> {
+        let tx_hashes: HashSet<&String> = block.transactions.iter().map(|tx| &tx.transaction_hash).collect();
+        outputs
+            .into_iter()
+            .filter(|u| tx_hashes.contains(&u.transaction_hash))
+            .map(|mut u| {
+                u.id = None;
+                u.status = "unspent".to_string();
+                u.block_hash = "pending".to_string();
+                u
+            })
+            .collect()
+    }
+
+    // Marks the block's transactions confirmed and drops pending transactions that conflict with them
+    pub async fn connect_block(db: &Database, mempool: &Mempool, block: &Block) -> Result<(), ApiError> {
+        DbOps::set_block_main_chain(db, &block.hash, true).await?;
+        let stale = mempool.remove_for_block(block);
+
+        let mut confirmed = HashSet::new();
+        let mut spent = Vec::new();
+        for tx in &block.transactions {
+            DbOps::upsert_confirmed_transaction(db, tx, &block.hash).await?;
+            confirmed.insert(tx.transaction_hash.clone());
+            spent.extend(tx.input_utxos.iter().cloned());
+        }
+        let mined: Vec<String> = confirmed.iter().cloned().collect();
+        DbOps::set_utxos_block_hash(db, &mined, &block.hash).await?;
+        DbOps::revive_orphaned_outputs(db, &mined).await?;
+        DbOps::set_utxos_status(db, &spent, "spent").await?;
+        let spent_set: HashSet<String> = spent.iter().cloned().collect();
+
+        // Pooled transactions whose nonce the block already used can never be mined
+        if !stale.is_empty() {
+            let hashes: Vec<String> = stale.iter().map(|tx| tx.transaction_hash.clone()).collect();
+            Self::drop_transactions(db, stale, TransactionStatus::Rejected, &spent_set).await?;
+            DbOps::remove_pending_transactions(db, &hashes).await?;
+        }
+
+        let conflicting: Vec<Transaction> = DbOps::get_pending_transactions_spending(db, &spent)
+            .await?
+            .into_iter()
+            .filter(|tx| !confirmed.contains(&tx.transaction_hash))
+            .collect();
+        Self::orphan_transactions(db, conflicting, &spent_set).await
+    }
+
+    // Returns the block's transactions to the mempool
+    async fn disconnect_block(db: &Database, mempool: &Mempool, block: &Block) -> Result<(), ApiError> {
+        DbOps::set_block_main_chain(db, &block.hash, false).await?;
+
+        let (coinbase, transactions): (Vec<Transaction>, Vec<Transaction>) =
+            DbOps::get_transactions_by_block_hash(db, &block.hash)
+                .await?
+                .into_iter()
+                .partition(Blockchain::is_coinbase);
+        let unmined: Vec<String> = block.transactions.iter().map(|tx| tx.transaction_hash.clone()).collect();
+        DbOps::set_utxos_block_hash(db, &unmined, "pending").await?;
+        for tx in &transactions {
+            DbOps::set_transaction_status(db, &tx.transaction_hash, TransactionStatus::InMempool, None).await?;
+        }
+        mempool.restore(db, transactions).await?;
+
+        // A block reward only exists in its block, so it and anything spending it are dropped
+        let reward_outputs: Vec<String> = coinbase.iter().flat_map(|tx| tx.output_ids()).collect();
+        mempool.remove_spenders(&reward_outputs);
+        Self::orphan_transactions(db, coinbase, &HashSet::new()).await
+    }
+
+    // Orphans the given transactions and everything spending their outputs. Inputs that the winning
+    // transactions did not spend become spendable again.
+    pub async fn orphan_transactions(
+        db: &Database,
+        transactions: Vec<Transaction>,
+        spent_by_chain: &HashSet<String>,
+    ) -> Result<(), ApiError> {
+        Self::drop_transactions(db, transactions, TransactionStatus::Orphaned, spent_by_chain).await
+    }
+
+    // Gives the given transactions `status` and orphans everything spending their outputs
+    pub async fn drop_transactions(
+        db: &Database,
+        transactions: Vec<Transaction>,
+        status: TransactionStatus,
+        spent_by_chain: &HashSet<String>,
+    ) -> Result<(), ApiError> {
+        let mut queue: Vec<(Transaction, TransactionStatus)> = transactions.into_iter().map(|tx| (tx, status)).collect();
+        while let Some((tx, status)) = queue.pop() {
+            log::warn!("Dropping transaction {} as {}", tx.transaction_hash, status);
+            DbOps::set_transaction_status(db, &tx.transaction_hash, status, None).await?;
+
+            let released: Vec<String> = tx
+                .input_utxos
+                .iter()
+                .filter(|id| !spent_by_chain.contains(*id))
+                .cloned()
+                .collect();
+            DbOps::set_utxos_status(db, &released, "unspent").await?;
+
+            let outputs = tx.output_ids();
+            DbOps::set_utxos_status(db, &outputs, "orphaned").await?;
+            for child in DbOps::get_pending_transactions_spending(db, &outputs).await? {
+                queue.push((child, TransactionStatus::Orphaned));
+            }
+        }
+        Ok(())
+    }
+
+    // Switches the main chain to the branch ending at `new_tip`
+    async fn reorganize(db: &Database, mempool: &Mempool, new_tip: &Block) -> Result<BlockOutcome, ApiError> {
+        // Walk back to the fork point on the main chain
+        let mut branch = vec![new_tip.clone()];
+        let mut cursor = new_tip.previous_hash.clone();
+        let fork = loop {
+            let block = DbOps::get_block_by_hash(db, &cursor)
+                .await?
+                .ok_or_else(|| ApiError::InternalError(format!("Missing ancestor block {}", cursor)))?;
+            if block.main_chain {
+                break block;
+            }
+            cursor = block.previous_hash.clone();
+            branch.push(block);
+        };
+        branch.reverse();
+
+        // Validate the whole branch on top of the state at the fork before changing anything
+        let main_blocks = DbOps::get_all_blocks(db).await?;
+        let utxos = DbOps::get_all_utxos(db).await?;
+        let mut state = DbOps::get_initial_chain_state(db, &utxos).await?;
+        for block in main_blocks.iter().filter(|b| b.index <= fork.index) {
+            state.apply_block(block, &utxos);
+        }
+        for (i, block) in branch.iter().enumerate() {
+            if let Err(e) = Blockchain::validate_block(block, &state, &utxos) {
+                // The invalid block and its descendants can never join the main chain, and the
+                // outputs they brought along are dropped with them
+                for invalid in &branch[i..] {
+                    let tx_hashes: Vec<String> =
+                        invalid.transactions.iter().map(|tx| tx.transaction_hash.clone()).collect();
+                    DbOps::delete_unconnected_outputs(db, &tx_hashes).await?;
+                    DbOps::delete_block(db, &invalid.hash).await?;
+                }
+                return Err(e.into());
+            }
+            state.apply_block(block, &utxos);
+        }
+
+        // Roll back the old branch tip first, then connect the new one. If that fails part way, the
+        // old branch is put back so the main chain is never left between the two.
+        TIP_STATE.lock().unwrap().take();
+        let old_branch: Vec<Block> = main_blocks.into_iter().filter(|b| b.index > fork.index).collect();
+        if let Err(e) = Self::switch_branch(db, mempool, &old_branch, &branch).await {
+            log::error!("Reorganization to {} failed, restoring the previous main chain: {}", new_tip.hash, e);
+            let restored = Self::switch_branch(db, mempool, &branch, &old_branch).await;
+            DbOps::repair_blockchain_state(db).await?;
+            restored?;
+            return Err(e);
+        }
+
+        DbOps::repair_blockchain_state(db).await?;
+        Self::cache_tip_state(state);
+        log::info!(
+            "Reorganized to {} at height {}: {} blocks disconnected, {} connected",
+            new_tip.hash,
+            new_tip.index,
+            old_branch.len(),
+            branch.len()
+        );
+
+        Ok(BlockOutcome::Reorganized {
+            disconnected: old_branch.len(),
+            connected: branch.len(),
+        })
+    }
+
+    // Disconnects `from` (oldest first, undone from the tip down) and connects `to` in order
+    async fn switch_branch(db: &Database, mempool: &Mempool, from: &[Block], to: &[Block]) -> Result<(), ApiError> {
+        for block in from.iter().rev() {
+            Self::disconnect_block(db, mempool, block).await?;
+        }
+        for block in to {
+            Self::connect_block(db, mempool, block).await?;
+        }
+        Ok(())
+    }
+}
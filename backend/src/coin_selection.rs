@@ -0,0 +1,335 @@
+use rand::seq::SliceRandom;
+use serde::{Deserialize, Serialize};
+use std::env;
+
+use crate::models::UTXO;
+
+// Change below this is not worth an output and goes to the miner instead
+pub const DEFAULT_DUST_THRESHOLD: f64 = 0.001;
+
+// Outputs need this many confirmations before payments may spend them
+pub const DEFAULT_MIN_CONFIRMATIONS: u64 = 1;
+
+// Branch-and-bound gives up after this many search steps and falls back to largest-first
+const BNB_MAX_TRIES: usize = 100_000;
+
+pub fn dust_threshold() -> f64 {
+    env::var("DUST_THRESHOLD")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_DUST_THRESHOLD)
+}
+
+pub fn min_confirmations() -> u64 {
+    env::var("MIN_CONFIRMATIONS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_MIN_CONFIRMATIONS)
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CoinSelection {
+    LargestFirst,
+    #[default]
+    BranchAndBound,
+    Random,
+    OldestFirst,
+}
+
+impl CoinSelection {
+    pub fn selector(self) -> Box<dyn CoinSelector> {
+        match self {
+            CoinSelection::LargestFirst => Box::new(LargestFirst),
+            CoinSelection::BranchAndBound => Box::new(BranchAndBound),
+            CoinSelection::Random => Box::new(RandomSelection),
+            CoinSelection::OldestFirst => Box::new(OldestFirst),
+        }
+    }
+}
+
+// Inputs chosen for a payment and the fee to pay. What is left after the target and fee is
+// either zero or at least the dust threshold; smaller remainders are added to the fee.
+#[derive(Debug, Clone)]
+pub struct Selection {
+    pub inputs: Vec<UTXO>,
+    pub fee: f64,
+}
+
+// Picks inputs covering `target` plus the fee. `fee_for(inputs, with_change)` gives the fee for a
+// transaction spending that many inputs, with or without a change output.
+pub trait CoinSelector {
+    fn select(
+        &self,
+        utxos: &[UTXO],
+        target: f64,
+        fee_for: &dyn Fn(usize, bool) -> f64,
+        dust_threshold: f64,
+    ) -> Option<Selection>;
+}
+
+// Spends the fewest, largest outputs
+pub struct LargestFirst;
+
+impl CoinSelector for LargestFirst {
+    fn select(
+        &self,
+        utxos: &[UTXO],
+        target: f64,
+        fee_for: &dyn Fn(usize, bool) -> f64,
+        dust_threshold: f64,
+    ) -> Option<Selection> {
+        let mut ordered = utxos.to_vec();
+        ordered.sort_by(|a, b| b.amount.total_cmp(&a.amount));
+        accumulate(ordered, target, fee_for, dust_threshold)
+    }
+}
+
+// Spends outputs in the order they were created, consolidating old coins over time
+pub struct OldestFirst;
+
+impl CoinSelector for OldestFirst {
+    fn select(
+        &self,
+        utxos: &[UTXO],
+        target: f64,
+        fee_for: &dyn Fn(usize, bool) -> f64,
+        dust_threshold: f64,
+    ) -> Option<Selection> {
+        let mut ordered = utxos.to_vec();
+        ordered.sort_by_key(|u| u.created_at);
+        accumulate(ordered, target, fee_for, dust_threshold)
+    }
+}
+
+// Spends outputs in random order so the choice of inputs says nothing about the wallet
+pub struct RandomSelection;
+
+impl CoinSelector for RandomSelection {
+    fn select(
+        &self,
+        utxos: &[UTXO],
+        target: f64,
+        fee_for: &dyn Fn(usize, bool) -> f64,
+        dust_threshold: f64,
+    ) -> Option<Selection> {
+        let mut ordered = utxos.to_vec();
+        ordered.shuffle(&mut rand::thread_rng());
+        accumulate(ordered, target, fee_for, dust_threshold)
+    }
+}
+
+// Searches for a set of inputs that pays the target and fee with less than the dust threshold
+// left over, so no change output is needed. Falls back to largest-first when there is none.
+pub struct BranchAndBound;
+
+impl CoinSelector for BranchAndBound {
+    fn select(
+        &self,
+        utxos: &[UTXO],
+        target: f64,
+        fee_for: &dyn Fn(usize, bool) -> f64,
+        dust_threshold: f64,
+    ) -> Option<Selection> {
+        let mut ordered = utxos.to_vec();
+        ordered.sort_by(|a, b| b.amount.total_cmp(&a.amount));
+
+        // remaining[i] is the total of ordered[i..], used to prune branches that cannot reach the target
+        let mut remaining = vec![0.0; ordered.len() + 1];
+        for i in (0..ordered.len()).rev() {
+            remaining[i] = remaining[i + 1] + ordered[i].amount;
+        }
+
+        let mut search = Search {
+            utxos: &ordered,
+            remaining: &remaining,
+            target,
+            fee_for,
+            dust_threshold,
+            chosen: Vec::new(),
+            best: None,
+            tries: 0,
+        };
+        search.explore(0, 0.0);
+
+        match search.best {
+            Some((indices, _)) => {
+                let inputs: Vec<UTXO> = indices.iter().map(|&i| ordered[i].clone()).collect();
+                Some(fold_change(inputs, target))
+            }
+            None => LargestFirst.select(utxos, target, fee_for, dust_threshold),
+        }
+    }
+}
+
+struct Search<'a> {
+    utxos: &'a [UTXO],
+    remaining: &'a [f64],
+    target: f64,
+    fee_for: &'a dyn Fn(usize, bool) -> f64,
+    dust_threshold: f64,
+    chosen: Vec<usize>,
+    // Chosen indices and the amount left over beyond target and fee
+    best: Option<(Vec<usize>, f64)>,
+    tries: usize,
+}
+
+impl Search<'_> {
+    fn explore(&mut self, index: usize, sum: f64) {
+        self.tries += 1;
+        if self.tries > BNB_MAX_TRIES || matches!(self.best, Some((_, waste)) if waste == 0.0) {
+            return;
+        }
+
+        if !self.chosen.is_empty() {
+            let needed = self.target + (self.fee_for)(self.chosen.len(), false);
+            if sum >= needed {
+                let waste = sum - needed;
+                if waste < self.dust_threshold && self.best.as_ref().is_none_or(|(_, best)| waste < *best) {
+                    self.best = Some((self.chosen.clone(), waste));
+                }
+                // Adding more inputs only adds waste
+                return;
+            }
+        }
+
+        if index == self.utxos.len() || sum + self.remaining[index] < self.target {
+            return;
+        }
+
+        // Include the next output, then try without it
+        self.chosen.push(index);
+        self.explore(index + 1, sum + self.utxos[index].amount);
+        self.chosen.pop();
+        self.explore(index + 1, sum);
+    }
+}
+
+// Takes outputs in the given order until they cover the target and fee
+fn accumulate(
+    ordered: Vec<UTXO>,
+    target: f64,
+    fee_for: &dyn Fn(usize, bool) -> f64,
+    dust_threshold: f64,
+) -> Option<Selection> {
+    let mut inputs = Vec::new();
+    let mut sum = 0.0;
+    for utxo in ordered {
+        sum += utxo.amount;
+        inputs.push(utxo);
+
+        let fee_without_change = fee_for(inputs.len(), false);
+        if sum < target + fee_without_change {
+            continue;
+        }
+
+        let fee_with_change = fee_for(inputs.len(), true);
+        let change = sum - target - fee_with_change;
+        return Some(if change >= dust_threshold {
+            Selection { inputs, fee: fee_with_change }
+        } else {
+            fold_change(inputs, target)
+        });
+    }
+    None
+}
+
+// Spends everything beyond the target as fee. Summed the way the transaction builder sums its
+// inputs so the change it computes comes out exactly zero.
+fn fold_change(inputs: Vec<UTXO>, target: f64) -> Selection {
+    let fee = inputs.iter().map(|u| u.amount).sum::<f64>() - target;
+    Selection { inputs, fee }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, Utc};
+
+    const DUST: f64 = 0.001;
+
+    fn utxos(amounts: &[f64]) -> Vec<UTXO> {
+        amounts
+            .iter()
+            .enumerate()
+            .map(|(i, &amount)| UTXO {
+                id: None,
+                utxo_id: format!("utxo-{}", i),
+                wallet_id: "sender".to_string(),
+                amount,
+                status: "unspent".to_string(),
+                block_hash: "block".to_string(),
+                transaction_hash: format!("tx-{}", i),
+                created_at: Utc::now() + Duration::seconds(i as i64),
+            })
+            .collect()
+    }
+
+    fn ids(selection: &Selection) -> Vec<&str> {
+        selection.inputs.iter().map(|u| u.utxo_id.as_str()).collect()
+    }
+
+    fn left_over(selection: &Selection, target: f64) -> f64 {
+        selection.inputs.iter().map(|u| u.amount).sum::<f64>() - target - selection.fee
+    }
+
+    #[test]
+    fn branch_and_bound_finds_exact_match_without_change() {
+        let selection = BranchAndBound
+            .select(&utxos(&[5.0, 3.0, 2.0]), 2.5, &|_, _| 0.5, DUST)
+            .unwrap();
+        assert_eq!(ids(&selection), ["utxo-1"]);
+        assert_eq!(selection.fee, 0.5);
+        assert_eq!(left_over(&selection, 2.5), 0.0);
+    }
+
+    #[test]
+    fn change_below_dust_threshold_goes_to_fee() {
+        let selection = LargestFirst
+            .select(&utxos(&[4.0, 1.0]), 3.4995, &|_, _| 0.5, DUST)
+            .unwrap();
+        assert_eq!(ids(&selection), ["utxo-0"]);
+        assert!((selection.fee - 0.5005).abs() < 1e-9);
+        assert_eq!(left_over(&selection, 3.4995), 0.0);
+    }
+
+    #[test]
+    fn change_at_dust_threshold_is_kept() {
+        let fee_for = |_: usize, with_change: bool| if with_change { 0.6 } else { 0.5 };
+        let selection = LargestFirst.select(&utxos(&[4.0, 1.0]), 3.0, &fee_for, DUST).unwrap();
+        assert_eq!(selection.fee, 0.6);
+        assert!((left_over(&selection, 3.0) - 0.4).abs() < 1e-9);
+    }
+
+    #[test]
+    fn branch_and_bound_falls_back_to_largest_first() {
+        let available = utxos(&[1.0, 5.0, 3.0]);
+        let fee_for = |inputs: usize, with_change: bool| 0.1 * inputs as f64 + if with_change { 0.05 } else { 0.0 };
+        let selection = BranchAndBound.select(&available, 1.0, &fee_for, DUST).unwrap();
+        let largest_first = LargestFirst.select(&available, 1.0, &fee_for, DUST).unwrap();
+        assert_eq!(ids(&selection), ["utxo-1"]);
+        assert_eq!(ids(&selection), ids(&largest_first));
+        assert_eq!(selection.fee, largest_first.fee);
+    }
+
+    #[test]
+    fn oldest_first_spends_in_creation_order() {
+        let selection = OldestFirst
+            .select(&utxos(&[1.0, 5.0, 3.0]), 1.5, &|_, _| 0.1, DUST)
+            .unwrap();
+        assert_eq!(ids(&selection), ["utxo-0", "utxo-1"]);
+    }
+
+    #[test]
+    fn every_strategy_fails_when_funds_are_short() {
+        let available = utxos(&[1.0, 2.0]);
+        for strategy in [
+            CoinSelection::LargestFirst,
+            CoinSelection::BranchAndBound,
+            CoinSelection::Random,
+            CoinSelection::OldestFirst,
+        ] {
+            assert!(strategy.selector().select(&available, 3.0, &|_, _| 0.1, DUST).is_none());
+        }
+    }
+}
@@ -1,7 +1,9 @@
-use sha2::{Sha256, Digest};
+use sha2::{Sha256, Sha512, Digest};
+use hmac::{Hmac, Mac};
 use hex;
 use rand::Rng;
 use bcrypt;
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
 
 pub fn hash_sha256(data: &[u8]) -> String {
     let mut hasher = Sha256::new();
@@ -10,15 +12,52 @@ pub fn hash_sha256(data: &[u8]) -> String {
     hex::encode(result)
 }
 
-pub fn generate_keypair() -> (String, String) {
-    let mut rng = rand::thread_rng();
-    let private_key: Vec<u8> = (0..32).map(|_| rng.gen()).collect();
-    let private_key_hex = hex::encode(&private_key);
-    
-    // Simplified public key generation (SHA256 of private key)
-    let public_key = hash_sha256(&private_key);
-    
-    (private_key_hex, public_key)
+fn decode_key(key_hex: &str) -> Option<[u8; 32]> {
+    hex::decode(key_hex).ok()?.try_into().ok()
+}
+
+// Words in a new recovery phrase; 24 words carry 256 bits of entropy
+const MNEMONIC_WORDS: usize = 24;
+
+// A fresh BIP39 recovery phrase from which the wallet's keypair is derived
+pub fn generate_mnemonic() -> String {
+    bip39::Mnemonic::generate(MNEMONIC_WORDS)
+        .expect("24 is a valid BIP39 word count")
+        .to_string()
+}
+
+// Derives the keypair (private, public; hex) from a BIP39 phrase and optional passphrase. The
+// Ed25519 secret is the SLIP-0010 master key of the BIP39 seed. Fails if the phrase's words or
+// checksum are invalid.
+pub fn keypair_from_mnemonic(phrase: &str, passphrase: &str) -> Option<(String, String)> {
+    let private_key = slip10_master_key(&mnemonic_seed(phrase, passphrase)?);
+    let signing_key = SigningKey::from_bytes(&private_key);
+    let public_key = hex::encode(signing_key.verifying_key().to_bytes());
+    Some((hex::encode(private_key), public_key))
+}
+
+// Words are matched case-insensitively and may be separated by any whitespace, as people copy them
+fn mnemonic_seed(phrase: &str, passphrase: &str) -> Option<[u8; 64]> {
+    let words: Vec<String> = phrase.split_whitespace().map(str::to_lowercase).collect();
+    let mnemonic = bip39::Mnemonic::parse_normalized(&words.join(" ")).ok()?;
+    Some(mnemonic.to_seed_normalized(passphrase))
+}
+
+fn slip10_master_key(seed: &[u8]) -> [u8; 32] {
+    let mut mac = Hmac::<Sha512>::new_from_slice(b"ed25519 seed").expect("HMAC takes keys of any length");
+    mac.update(seed);
+    let master = mac.finalize().into_bytes();
+    master[..32].try_into().expect("HMAC-SHA512 output is 64 bytes")
+}
+
+// Hex Ed25519 public key of a hex private key
+pub fn public_key_from_private(private_key: &str) -> Option<String> {
+    decode_key(private_key).map(|seed| hex::encode(SigningKey::from_bytes(&seed).verifying_key().to_bytes()))
+}
+
+// Whether the hex string is an Ed25519 public key, e.g. one generated outside this node
+pub fn is_valid_public_key(public_key: &str) -> bool {
+    decode_key(public_key).is_some_and(|k| VerifyingKey::from_bytes(&k).is_ok())
 }
 
 pub fn generate_wallet_id(public_key: &str) -> String {
@@ -26,16 +65,23 @@ pub fn generate_wallet_id(public_key: &str) -> String {
 }
 
 pub fn sign_transaction(data: &str, private_key: &str) -> String {
-    // Simplified signing: hash(data || private_key)
-    let combined = format!("{}{}", data, private_key);
-    hash_sha256(combined.as_bytes())
+    // Ed25519 signature over the payload, hex encoded
+    match decode_key(private_key) {
+        Some(seed) => hex::encode(SigningKey::from_bytes(&seed).sign(data.as_bytes()).to_bytes()),
+        None => String::new(),
+    }
 }
 
 pub fn verify_signature(data: &str, signature: &str, public_key: &str) -> bool {
-    // Simplified verification: regenerate signature and compare
-    // In production, use proper RSA or ECDSA
-    let expected_signature = hash_sha256(format!("{}{}", data, public_key).as_bytes());
-    signature == expected_signature
+    let public_key = match decode_key(public_key).and_then(|k| VerifyingKey::from_bytes(&k).ok()) {
+        Some(key) => key,
+        None => return false,
+    };
+    let signature = match hex::decode(signature).ok().and_then(|s| Signature::from_slice(&s).ok()) {
+        Some(sig) => sig,
+        None => return false,
+    };
+    public_key.verify(data.as_bytes(), &signature).is_ok()
 }
 
 pub fn generate_otp() -> String {
@@ -61,14 +107,60 @@ pub fn calculate_zakat(balance: f64) -> f64 {
     }
 }
 
-pub fn validate_transaction_signature(
+// The fee is appended only when one is paid, so signatures on older fee-less transactions still verify
+pub fn transaction_payload(
     sender_wallet_id: &str,
     recipient_wallet_id: &str,
     amount: f64,
+    fee: f64,
     timestamp: i64,
-    signature: &str,
-    public_key: &str,
-) -> bool {
+) -> String {
     let data = format!("{}{}{}{}", sender_wallet_id, recipient_wallet_id, amount, timestamp);
-    verify_signature(&data, signature, public_key)
+    if fee > 0.0 {
+        format!("{}:{}", data, fee)
+    } else {
+        data
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VECTOR_PHRASE: &str = "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about";
+
+    // BIP39 test vector 1 (English, passphrase "TREZOR")
+    #[test]
+    fn mnemonic_seed_matches_bip39_vector() {
+        let seed = mnemonic_seed(VECTOR_PHRASE, "TREZOR").unwrap();
+        assert_eq!(
+            hex::encode(seed),
+            "c55257c360c07c72029aebc1b53c05ed0362ada38ead3e3e9efa3708e53495531f09a6987599d18264c1e1c92f2cf141630c7a3c4ab7c81b2f001698e7463b04"
+        );
+    }
+
+    // SLIP-0010 ed25519 test vector 1, chain m
+    #[test]
+    fn master_key_matches_slip10_vector() {
+        let seed = hex::decode("000102030405060708090a0b0c0d0e0f").unwrap();
+        let private_key = slip10_master_key(&seed);
+        assert_eq!(hex::encode(private_key), "2b4be7f19ee27bbf30c667b642d5f4aa69fd169872f8fc3059c08ebae2eb19e7");
+        assert_eq!(
+            hex::encode(SigningKey::from_bytes(&private_key).verifying_key().to_bytes()),
+            "a4b2856bfec510abab89753fac1ac0e1112364e7d250545963f135f2a33188ed"
+        );
+    }
+
+    #[test]
+    fn keypair_ignores_case_and_spacing() {
+        let expected = keypair_from_mnemonic(VECTOR_PHRASE, "TREZOR").unwrap();
+        let typed = format!("  {}\n", VECTOR_PHRASE.replacen("abandon", "Abandon", 1).replace("about", "ABOUT"));
+        assert_eq!(keypair_from_mnemonic(&typed, "TREZOR"), Some(expected.clone()));
+        assert_ne!(keypair_from_mnemonic(VECTOR_PHRASE, "").unwrap(), expected);
+    }
+
+    #[test]
+    fn keypair_rejects_bad_checksum() {
+        assert!(keypair_from_mnemonic(&["abandon"; 12].join(" "), "").is_none());
+    }
 }
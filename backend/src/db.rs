@@ -1,12 +1,25 @@
 use mongodb::Database;
-use crate::models::{User, Block, Transaction, UTXO, BlockchainState, SystemLog};
+use crate::models::{
+    User, Beneficiary, Block, BlockHeader, Transaction, TransactionStatus, UTXO, BlockchainState, SyncProgress, SystemLog,
+    ScheduledConsolidation, IdempotencyRecord, HistoryDirection, HistoryQuery, SortOrder,
+};
 use crate::error::ApiError;
+use crate::crypto::{generate_wallet_id, public_key_from_private};
+use crate::blockchain::{Blockchain, ChainState};
 use mongodb::bson::{doc, Document};
-use chrono::Utc;
+use chrono::{DateTime, Utc};
+use std::collections::{HashMap, HashSet};
+
+// Inserting a key is retried this often when it keeps disappearing between the insert and the lookup
+const IDEMPOTENCY_RESERVE_ATTEMPTS: usize = 3;
+// Storing a finished request's transaction hash is retried this often before giving up
+const IDEMPOTENCY_COMPLETE_ATTEMPTS: usize = 3;
 
 pub struct Database;
 
 impl Database {
+    const UNCONFIRMED_STATUSES: [&'static str; 2] = ["pending", "in-mempool"];
+
     pub async fn create_user(db: &mongodb::Database, user: &User) -> Result<(), ApiError> {
         db.collection("users")
             .insert_one(user, None)
@@ -31,6 +44,76 @@ impl Database {
             .map_err(|e| ApiError::DatabaseError(e.to_string()))
     }
 
+    // Wallets created before Ed25519 signing have public_key = SHA-256 of the private key, which can
+    // never verify a signature. Each is re-keyed to the Ed25519 key of its stored private key, which
+    // gives it a new wallet ID, and its unspent outputs and address book entries follow it there.
+    pub async fn migrate_legacy_keys(db: &mongodb::Database) -> Result<usize, ApiError> {
+        let users = db.collection::<User>("users");
+        let mut cursor = users
+            .find(doc! {}, None)
+            .await
+            .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+        let mut legacy = Vec::new();
+        while cursor.advance().await.map_err(|e| ApiError::DatabaseError(e.to_string()))? {
+            let user: User = cursor.deserialize_current().map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+            if let Some(public_key) = public_key_from_private(&user.private_key_encrypted) {
+                if public_key != user.public_key {
+                    legacy.push((user.wallet_id, public_key));
+                }
+            }
+        }
+
+        for (old_wallet_id, public_key) in &legacy {
+            let wallet_id = generate_wallet_id(public_key);
+            log::warn!("Re-keying legacy wallet {} to Ed25519 wallet {}", old_wallet_id, wallet_id);
+            users
+                .update_one(
+                    doc! { "wallet_id": old_wallet_id },
+                    doc! { "$set": { "wallet_id": &wallet_id, "public_key": public_key } },
+                    None,
+                )
+                .await
+                .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+            db.collection::<UTXO>("utxos")
+                .update_many(
+                    doc! { "wallet_id": old_wallet_id, "status": { "$ne": "spent" } },
+                    doc! { "$set": { "wallet_id": &wallet_id } },
+                    None,
+                )
+                .await
+                .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+            users
+                .update_many(
+                    doc! { "beneficiaries.wallet_id": old_wallet_id },
+                    doc! { "$set": { "beneficiaries.$[entry].wallet_id": &wallet_id } },
+                    mongodb::options::UpdateOptions::builder()
+                        .array_filters(vec![doc! { "entry.wallet_id": old_wallet_id }])
+                        .build(),
+                )
+                .await
+                .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+        }
+        Ok(legacy.len())
+    }
+
+    // Points a restored wallet at new account details
+    pub async fn relink_user(db: &mongodb::Database, user: &User) -> Result<(), ApiError> {
+        db.collection::<User>("users")
+            .update_one(
+                doc! { "wallet_id": &user.wallet_id },
+                doc! { "$set": {
+                    "email": &user.email,
+                    "full_name": &user.full_name,
+                    "cnic": &user.cnic,
+                    "password_hash": &user.password_hash,
+                } },
+                None,
+            )
+            .await
+            .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+        Ok(())
+    }
+
     pub async fn update_user_zakat(db: &mongodb::Database, wallet_id: &str, zakat_amount: f64) -> Result<(), ApiError> {
         db.collection("users")
             .update_one(
@@ -43,6 +126,60 @@ impl Database {
         Ok(())
     }
 
+    // Saves a beneficiary unless the user already has one for that wallet. Returns whether it was added.
+    pub async fn add_beneficiary(
+        db: &mongodb::Database,
+        wallet_id: &str,
+        beneficiary: &Beneficiary,
+    ) -> Result<bool, ApiError> {
+        let entry = mongodb::bson::to_bson(beneficiary).map_err(|e| ApiError::InternalError(e.to_string()))?;
+        let result = db
+            .collection::<User>("users")
+            .update_one(
+                doc! { "wallet_id": wallet_id, "beneficiaries.wallet_id": { "$ne": &beneficiary.wallet_id } },
+                doc! { "$push": { "beneficiaries": entry } },
+                None,
+            )
+            .await
+            .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+        Ok(result.modified_count > 0)
+    }
+
+    pub async fn rename_beneficiary(
+        db: &mongodb::Database,
+        wallet_id: &str,
+        beneficiary_wallet_id: &str,
+        nickname: &str,
+    ) -> Result<bool, ApiError> {
+        let result = db
+            .collection::<User>("users")
+            .update_one(
+                doc! { "wallet_id": wallet_id, "beneficiaries.wallet_id": beneficiary_wallet_id },
+                doc! { "$set": { "beneficiaries.$.nickname": nickname } },
+                None,
+            )
+            .await
+            .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+        Ok(result.matched_count > 0)
+    }
+
+    pub async fn remove_beneficiary(
+        db: &mongodb::Database,
+        wallet_id: &str,
+        beneficiary_wallet_id: &str,
+    ) -> Result<bool, ApiError> {
+        let result = db
+            .collection::<User>("users")
+            .update_one(
+                doc! { "wallet_id": wallet_id },
+                doc! { "$pull": { "beneficiaries": { "wallet_id": beneficiary_wallet_id } } },
+                None,
+            )
+            .await
+            .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+        Ok(result.modified_count > 0)
+    }
+
     pub async fn get_utxos_for_wallet(db: &mongodb::Database, wallet_id: &str) -> Result<Vec<UTXO>, ApiError> {
         let collection = db.collection::<UTXO>("utxos");
         let mut cursor = collection
@@ -57,6 +194,94 @@ impl Database {
         Ok(utxos)
     }
 
+    // Whether the ledger has ever credited the wallet, spent or not
+    pub async fn wallet_has_utxos(db: &mongodb::Database, wallet_id: &str) -> Result<bool, ApiError> {
+        let utxo = db
+            .collection::<UTXO>("utxos")
+            .find_one(doc! { "wallet_id": wallet_id }, None)
+            .await
+            .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+        Ok(utxo.is_some())
+    }
+
+    // Unspent outputs of the wallet with at least `min_confirmations` confirmations
+    pub async fn get_spendable_utxos(
+        db: &mongodb::Database,
+        wallet_id: &str,
+        min_confirmations: u64,
+    ) -> Result<Vec<UTXO>, ApiError> {
+        let utxos = Self::get_utxos_for_wallet(db, wallet_id).await?;
+        if min_confirmations == 0 {
+            return Ok(utxos);
+        }
+        let confirmations = Self::get_utxo_confirmations(db, &utxos).await?;
+        Ok(utxos
+            .into_iter()
+            .filter(|u| confirmations.get(&u.utxo_id).copied().unwrap_or(0) >= min_confirmations)
+            .collect())
+    }
+
+    pub async fn get_all_utxos(db: &mongodb::Database) -> Result<HashMap<String, UTXO>, ApiError> {
+        let collection = db.collection::<UTXO>("utxos");
+        let mut cursor = collection
+            .find(doc! {}, None)
+            .await
+            .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+
+        let mut utxos = HashMap::new();
+        while cursor.advance().await.map_err(|e| ApiError::DatabaseError(e.to_string()))? {
+            let utxo: UTXO = cursor.deserialize_current().map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+            utxos.insert(utxo.utxo_id.clone(), utxo);
+        }
+        Ok(utxos)
+    }
+
+    pub async fn get_utxos_by_ids(db: &mongodb::Database, utxo_ids: &[String]) -> Result<Vec<UTXO>, ApiError> {
+        let collection = db.collection::<UTXO>("utxos");
+        let mut cursor = collection
+            .find(doc! { "utxo_id": { "$in": utxo_ids } }, None)
+            .await
+            .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+
+        let mut utxos = Vec::new();
+        while cursor.advance().await.map_err(|e| ApiError::DatabaseError(e.to_string()))? {
+            utxos.push(cursor.deserialize_current().map_err(|e| ApiError::DatabaseError(e.to_string()))?);
+        }
+        Ok(utxos)
+    }
+
+    pub async fn get_utxos_by_transaction_hashes(
+        db: &mongodb::Database,
+        tx_hashes: &[String],
+    ) -> Result<Vec<UTXO>, ApiError> {
+        let collection = db.collection::<UTXO>("utxos");
+        let mut cursor = collection
+            .find(doc! { "transaction_hash": { "$in": tx_hashes } }, None)
+            .await
+            .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+
+        let mut utxos = Vec::new();
+        while cursor.advance().await.map_err(|e| ApiError::DatabaseError(e.to_string()))? {
+            utxos.push(cursor.deserialize_current().map_err(|e| ApiError::DatabaseError(e.to_string()))?);
+        }
+        Ok(utxos)
+    }
+
+    // Stores an output announced by a peer unless we already track it
+    pub async fn insert_utxo_if_missing(db: &mongodb::Database, utxo: &UTXO) -> Result<(), ApiError> {
+        let mut utxo = utxo.clone();
+        utxo.id = None;
+        db.collection::<UTXO>("utxos")
+            .update_one(
+                doc! { "utxo_id": &utxo.utxo_id },
+                doc! { "$setOnInsert": mongodb::bson::to_document(&utxo).map_err(|e| ApiError::InternalError(e.to_string()))? },
+                mongodb::options::UpdateOptions::builder().upsert(true).build(),
+            )
+            .await
+            .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+        Ok(())
+    }
+
     pub async fn create_utxo(db: &mongodb::Database, utxo: &UTXO) -> Result<(), ApiError> {
         db.collection("utxos")
             .insert_one(utxo, None)
@@ -65,11 +290,11 @@ impl Database {
         Ok(())
     }
 
-    pub async fn mark_utxo_spent(db: &mongodb::Database, utxo_id: &str) -> Result<(), ApiError> {
-        db.collection("utxos")
-            .update_one(
-                doc! { "utxo_id": utxo_id },
-                doc! { "$set": { "status": "spent" } },
+    pub async fn set_utxos_status(db: &mongodb::Database, utxo_ids: &[String], status: &str) -> Result<(), ApiError> {
+        db.collection::<UTXO>("utxos")
+            .update_many(
+                doc! { "utxo_id": { "$in": utxo_ids } },
+                doc! { "$set": { "status": status } },
                 None,
             )
             .await
@@ -85,20 +310,439 @@ impl Database {
         Ok(())
     }
 
-    pub async fn get_transactions_for_wallet(db: &mongodb::Database, wallet_id: &str) -> Result<Vec<Transaction>, ApiError> {
+    // When the mempool admitted a transaction, kept beside it so a restart keeps its real arrival time
+    pub async fn set_mempool_added_at(
+        db: &mongodb::Database,
+        tx_hash: &str,
+        added_at: DateTime<Utc>,
+    ) -> Result<(), ApiError> {
+        let added_at = mongodb::bson::to_bson(&added_at).map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+        db.collection::<Transaction>("transactions")
+            .update_one(
+                doc! { "transaction_hash": tx_hash },
+                doc! { "$set": { "mempool_added_at": added_at } },
+                None,
+            )
+            .await
+            .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+        Ok(())
+    }
+
+    pub async fn get_mempool_added_at(
+        db: &mongodb::Database,
+        tx_hashes: &[String],
+    ) -> Result<HashMap<String, DateTime<Utc>>, ApiError> {
+        let mut cursor = db
+            .collection::<Document>("transactions")
+            .find(
+                doc! { "transaction_hash": { "$in": tx_hashes }, "mempool_added_at": { "$exists": true } },
+                None,
+            )
+            .await
+            .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+
+        let mut added_at = HashMap::new();
+        while cursor.advance().await.map_err(|e| ApiError::DatabaseError(e.to_string()))? {
+            let document = cursor.deserialize_current().map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+            let (Ok(hash), Ok(time)) = (document.get_str("transaction_hash"), document.get_str("mempool_added_at")) else {
+                continue;
+            };
+            if let Ok(time) = DateTime::parse_from_rfc3339(time) {
+                added_at.insert(hash.to_string(), time.with_timezone(&Utc));
+            }
+        }
+        Ok(added_at)
+    }
+
+    // Pending transactions in the order they were created, used to rebuild the mempool
+    pub async fn get_pending_transactions(db: &mongodb::Database) -> Result<Vec<Transaction>, ApiError> {
+        let options = mongodb::options::FindOptions::builder().sort(doc! { "timestamp": 1 }).build();
+        let mut cursor = db
+            .collection::<Transaction>("transactions")
+            .find(doc! { "status": { "$in": Self::UNCONFIRMED_STATUSES.to_vec() } }, options)
+            .await
+            .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+
+        let mut transactions = Vec::new();
+        while cursor.advance().await.map_err(|e| ApiError::DatabaseError(e.to_string()))? {
+            transactions.push(cursor.deserialize_current().map_err(|e| ApiError::DatabaseError(e.to_string()))?);
+        }
+        Ok(transactions)
+    }
+
+    pub async fn get_transaction_by_hash(db: &mongodb::Database, tx_hash: &str) -> Result<Option<Transaction>, ApiError> {
+        let collection = db.collection::<Transaction>("transactions");
+        collection
+            .find_one(doc! { "transaction_hash": tx_hash }, None)
+            .await
+            .map_err(|e| ApiError::DatabaseError(e.to_string()))
+    }
+
+    pub async fn get_transactions_by_hashes(db: &mongodb::Database, tx_hashes: &[String]) -> Result<Vec<Transaction>, ApiError> {
         let collection = db.collection::<Transaction>("transactions");
+        let mut cursor = collection
+            .find(doc! { "transaction_hash": { "$in": tx_hashes } }, None)
+            .await
+            .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+
+        let mut transactions = Vec::new();
+        while cursor.advance().await.map_err(|e| ApiError::DatabaseError(e.to_string()))? {
+            transactions.push(cursor.deserialize_current().map_err(|e| ApiError::DatabaseError(e.to_string()))?);
+        }
+        Ok(transactions)
+    }
+
+    // Confirmations of each given block: main-chain blocks on top of it, plus itself. Blocks not
+    // on the main chain, or unknown, are left out.
+    pub async fn get_block_confirmations(
+        db: &mongodb::Database,
+        block_hashes: &[String],
+    ) -> Result<HashMap<String, u64>, ApiError> {
+        let tip = Self::get_chain_tip(db).await?;
+        Ok(Self::get_blocks_by_hashes(db, block_hashes)
+            .await?
+            .into_iter()
+            .filter(|b| b.main_chain)
+            .map(|b| (b.hash, tip.index.saturating_sub(b.index) + 1))
+            .collect())
+    }
+
+    // Confirmations of each transaction; zero unless it is confirmed on the main chain
+    pub async fn get_transaction_confirmations(
+        db: &mongodb::Database,
+        transactions: &[Transaction],
+    ) -> Result<HashMap<String, u64>, ApiError> {
+        let block_hashes: Vec<String> = transactions
+            .iter()
+            .filter(|tx| tx.status == TransactionStatus::Confirmed)
+            .filter_map(|tx| tx.block_hash.clone())
+            .collect();
+        let depths = Self::get_block_confirmations(db, &block_hashes).await?;
+        Ok(transactions
+            .iter()
+            .map(|tx| {
+                let confirmations = match &tx.block_hash {
+                    Some(hash) if tx.status == TransactionStatus::Confirmed => depths.get(hash).copied().unwrap_or(0),
+                    _ => 0,
+                };
+                (tx.transaction_hash.clone(), confirmations)
+            })
+            .collect())
+    }
+
+    // Confirmations of the block that created each output. Outputs of unmined transactions have
+    // none; allocations outside any block count as deep as the chain.
+    pub async fn get_utxo_confirmations(
+        db: &mongodb::Database,
+        utxos: &[UTXO],
+    ) -> Result<HashMap<String, u64>, ApiError> {
+        let tip = Self::get_chain_tip(db).await?;
+        let tx_hashes: Vec<String> = utxos.iter().map(|u| u.transaction_hash.clone()).collect();
+        let transactions: HashMap<String, Transaction> = Self::get_transactions_by_hashes(db, &tx_hashes)
+            .await?
+            .into_iter()
+            .map(|tx| (tx.transaction_hash.clone(), tx))
+            .collect();
+
+        // The block each output was mined in, if any. Outputs created before their block hash
+        // was recorded still say "pending", so the transaction's block is preferred.
+        let block_hash_of = |utxo: &UTXO| -> Option<String> {
+            match transactions.get(&utxo.transaction_hash) {
+                Some(tx) if tx.status == TransactionStatus::Confirmed => tx.block_hash.clone(),
+                Some(_) => None,
+                None if utxo.block_hash == "pending" => None,
+                None => Some(utxo.block_hash.clone()),
+            }
+        };
+        let block_hashes: Vec<String> = utxos.iter().filter_map(block_hash_of).collect();
+        let blocks: HashMap<String, Block> = Self::get_blocks_by_hashes(db, &block_hashes)
+            .await?
+            .into_iter()
+            .map(|b| (b.hash.clone(), b))
+            .collect();
+
+        Ok(utxos
+            .iter()
+            .map(|utxo| {
+                let confirmations = match block_hash_of(utxo) {
+                    None => 0,
+                    Some(hash) => match blocks.get(&hash) {
+                        Some(block) if block.main_chain => tip.index.saturating_sub(block.index) + 1,
+                        Some(_) => 0,
+                        None => tip.index + 1,
+                    },
+                };
+                (utxo.utxo_id.clone(), confirmations)
+            })
+            .collect())
+    }
+
+    // Highest nonce the wallet has signed, or only counting transactions confirmed on the main chain
+    pub async fn get_last_nonce(
+        db: &mongodb::Database,
+        wallet_id: &str,
+        confirmed_only: bool,
+    ) -> Result<Option<u64>, ApiError> {
+        let mut filter = doc! { "sender_wallet_id": wallet_id, "nonce": { "$exists": true } };
+        if confirmed_only {
+            filter.insert("status", TransactionStatus::Confirmed.as_str());
+        }
+        let options = mongodb::options::FindOneOptions::builder().sort(doc! { "nonce": -1 }).build();
+        let tx = db
+            .collection::<Transaction>("transactions")
+            .find_one(filter, options)
+            .await
+            .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+        Ok(tx.and_then(|tx| tx.nonce))
+    }
+
+    // Confirmed transactions touching the wallet up to `until`, oldest first
+    pub async fn get_confirmed_transactions_for_wallet(
+        db: &mongodb::Database,
+        wallet_id: &str,
+        until: &chrono::DateTime<Utc>,
+    ) -> Result<Vec<Transaction>, ApiError> {
+        let until = mongodb::bson::to_bson(until).map_err(|e| ApiError::DatabaseError(e.to_string()))?;
         let filter = doc! {
+            "status": TransactionStatus::Confirmed.as_str(),
+            "timestamp": { "$lte": until },
             "$or": [
                 { "sender_wallet_id": wallet_id },
-                { "recipient_wallet_id": wallet_id }
+                { "recipient_wallet_id": wallet_id },
+                { "outputs.wallet_id": wallet_id }
             ]
         };
+        let options = mongodb::options::FindOptions::builder()
+            .sort(doc! { "timestamp": 1, "transaction_hash": 1 })
+            .build();
+        let mut cursor = db
+            .collection::<Transaction>("transactions")
+            .find(filter, options)
+            .await
+            .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
 
-        let mut cursor = collection
+        let mut transactions = Vec::new();
+        while cursor.advance().await.map_err(|e| ApiError::DatabaseError(e.to_string()))? {
+            transactions.push(cursor.deserialize_current().map_err(|e| ApiError::DatabaseError(e.to_string()))?);
+        }
+        Ok(transactions)
+    }
+
+    // Outputs credited to the wallet up to `until` without any recorded transaction, i.e. funds
+    // allocated outside the chain. Spent ones are included since they were credited all the same.
+    pub async fn get_wallet_allocations(
+        db: &mongodb::Database,
+        wallet_id: &str,
+        until: &chrono::DateTime<Utc>,
+    ) -> Result<Vec<UTXO>, ApiError> {
+        let until = mongodb::bson::to_bson(until).map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+        let filter = doc! {
+            "wallet_id": wallet_id,
+            "status": { "$ne": "orphaned" },
+            "block_hash": { "$ne": "pending" },
+            "created_at": { "$lte": until }
+        };
+        let mut cursor = db
+            .collection::<UTXO>("utxos")
             .find(filter, None)
             .await
             .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
 
+        let mut utxos: Vec<UTXO> = Vec::new();
+        while cursor.advance().await.map_err(|e| ApiError::DatabaseError(e.to_string()))? {
+            utxos.push(cursor.deserialize_current().map_err(|e| ApiError::DatabaseError(e.to_string()))?);
+        }
+
+        let tx_hashes: Vec<String> = utxos.iter().map(|u| u.transaction_hash.clone()).collect();
+        let recorded: HashSet<String> = Self::get_transactions_by_hashes(db, &tx_hashes)
+            .await?
+            .into_iter()
+            .map(|tx| tx.transaction_hash)
+            .collect();
+        Ok(utxos.into_iter().filter(|u| !recorded.contains(&u.transaction_hash)).collect())
+    }
+
+    // One page of a wallet's history ordered by timestamp, then hash, starting after `after`. Amount
+    // filters depend on how much of a batch went to this wallet and are applied by the caller.
+    pub async fn get_wallet_history(
+        db: &mongodb::Database,
+        wallet_id: &str,
+        query: &HistoryQuery,
+        after: Option<&Transaction>,
+        limit: i64,
+    ) -> Result<Vec<Transaction>, ApiError> {
+        let to_bson = |value: &chrono::DateTime<Utc>| {
+            mongodb::bson::to_bson(value).map_err(|e| ApiError::DatabaseError(e.to_string()))
+        };
+        let received = doc! {
+            "sender_wallet_id": { "$ne": wallet_id },
+            "$or": [
+                { "recipient_wallet_id": wallet_id },
+                { "outputs.wallet_id": wallet_id }
+            ]
+        };
+
+        let mut conditions = vec![match query.direction {
+            Some(HistoryDirection::Sent) => doc! { "sender_wallet_id": wallet_id },
+            Some(HistoryDirection::Received) => received.clone(),
+            None => doc! {
+                "$or": [
+                    { "sender_wallet_id": wallet_id },
+                    { "recipient_wallet_id": wallet_id },
+                    { "outputs.wallet_id": wallet_id }
+                ]
+            },
+        }];
+        if let Some(status) = query.status {
+            conditions.push(doc! { "status": status.as_str() });
+        }
+        if let Some(from) = &query.from {
+            conditions.push(doc! { "timestamp": { "$gte": to_bson(from)? } });
+        }
+        if let Some(to) = &query.to {
+            conditions.push(doc! { "timestamp": { "$lte": to_bson(to)? } });
+        }
+        if let Some(counterparty) = &query.counterparty {
+            // The other side is the sender of what this wallet received, or any recipient of what it sent
+            conditions.push(doc! {
+                "$or": [
+                    { "sender_wallet_id": wallet_id, "recipient_wallet_id": counterparty },
+                    { "sender_wallet_id": wallet_id, "outputs.wallet_id": counterparty },
+                    { "$and": [received, { "sender_wallet_id": counterparty }] }
+                ]
+            });
+        }
+
+        let (order, past) = match query.sort {
+            SortOrder::Asc => (1, "$gt"),
+            SortOrder::Desc => (-1, "$lt"),
+        };
+        if let Some(after) = after {
+            let timestamp = to_bson(&after.timestamp)?;
+            conditions.push(doc! {
+                "$or": [
+                    { "timestamp": { past: timestamp.clone() } },
+                    { "timestamp": timestamp, "transaction_hash": { past: &after.transaction_hash } }
+                ]
+            });
+        }
+
+        let options = mongodb::options::FindOptions::builder()
+            .sort(doc! { "timestamp": order, "transaction_hash": order })
+            .limit(limit)
+            .build();
+        let mut cursor = db
+            .collection::<Transaction>("transactions")
+            .find(doc! { "$and": conditions }, options)
+            .await
+            .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+
+        let mut transactions = Vec::new();
+        while cursor.advance().await.map_err(|e| ApiError::DatabaseError(e.to_string()))? {
+            transactions.push(cursor.deserialize_current().map_err(|e| ApiError::DatabaseError(e.to_string()))?);
+        }
+        Ok(transactions)
+    }
+
+    pub async fn get_transaction_hashes(db: &mongodb::Database) -> Result<HashSet<String>, ApiError> {
+        let hashes = db
+            .collection::<Transaction>("transactions")
+            .distinct("transaction_hash", None, None)
+            .await
+            .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+        Ok(hashes.into_iter().filter_map(|h| h.as_str().map(str::to_string)).collect())
+    }
+
+    pub async fn set_transaction_status(
+        db: &mongodb::Database,
+        tx_hash: &str,
+        status: TransactionStatus,
+        block_hash: Option<&str>,
+    ) -> Result<(), ApiError> {
+        db.collection::<Transaction>("transactions")
+            .update_one(
+                doc! { "transaction_hash": tx_hash },
+                doc! { "$set": { "status": status.as_str(), "block_hash": block_hash } },
+                None,
+            )
+            .await
+            .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+        Ok(())
+    }
+
+    // Stores the block's copy of a transaction, which may come from another node, as confirmed
+    pub async fn upsert_confirmed_transaction(
+        db: &mongodb::Database,
+        tx: &Transaction,
+        block_hash: &str,
+    ) -> Result<(), ApiError> {
+        let mut confirmed = tx.clone();
+        confirmed.id = None;
+        confirmed.status = TransactionStatus::Confirmed;
+        confirmed.block_hash = Some(block_hash.to_string());
+        db.collection::<Transaction>("transactions")
+            .replace_one(
+                doc! { "transaction_hash": &tx.transaction_hash },
+                confirmed,
+                mongodb::options::ReplaceOptions::builder().upsert(true).build(),
+            )
+            .await
+            .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+        Ok(())
+    }
+
+    // Records the block that created the transactions' outputs, or "pending" when it is disconnected
+    pub async fn set_utxos_block_hash(
+        db: &mongodb::Database,
+        tx_hashes: &[String],
+        block_hash: &str,
+    ) -> Result<(), ApiError> {
+        db.collection::<UTXO>("utxos")
+            .update_many(
+                doc! { "transaction_hash": { "$in": tx_hashes } },
+                doc! { "$set": { "block_hash": block_hash } },
+                None,
+            )
+            .await
+            .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+        Ok(())
+    }
+
+    // Outputs orphaned by an earlier reorg become spendable again when their transaction is mined
+    pub async fn revive_orphaned_outputs(db: &mongodb::Database, tx_hashes: &[String]) -> Result<(), ApiError> {
+        db.collection::<UTXO>("utxos")
+            .update_many(
+                doc! { "transaction_hash": { "$in": tx_hashes }, "status": "orphaned" },
+                doc! { "$set": { "status": "unspent" } },
+                None,
+            )
+            .await
+            .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+        Ok(())
+    }
+
+    // Removes side-chain outputs of the given transactions that no main-chain block or pool entry uses
+    pub async fn delete_unconnected_outputs(db: &mongodb::Database, tx_hashes: &[String]) -> Result<(), ApiError> {
+        db.collection::<UTXO>("utxos")
+            .delete_many(
+                doc! { "transaction_hash": { "$in": tx_hashes }, "block_hash": "pending", "status": "orphaned" },
+                None,
+            )
+            .await
+            .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+        Ok(())
+    }
+
+    pub async fn get_pending_transactions_spending(
+        db: &mongodb::Database,
+        utxo_ids: &[String],
+    ) -> Result<Vec<Transaction>, ApiError> {
+        let collection = db.collection::<Transaction>("transactions");
+        let mut cursor = collection
+            .find(doc! { "status": { "$in": Self::UNCONFIRMED_STATUSES.to_vec() }, "input_utxos": { "$in": utxo_ids } }, None)
+            .await
+            .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+
         let mut transactions = Vec::new();
         while cursor.advance().await.map_err(|e| ApiError::DatabaseError(e.to_string()))? {
             transactions.push(cursor.deserialize_current().map_err(|e| ApiError::DatabaseError(e.to_string()))?);
@@ -131,7 +775,70 @@ impl Database {
     pub async fn get_all_blocks(db: &mongodb::Database) -> Result<Vec<Block>, ApiError> {
         let collection = db.collection::<Block>("blocks");
         let mut cursor = collection
-            .find(doc! {}, None)
+            .find(
+                doc! { "main_chain": { "$ne": false } },
+                Some(mongodb::options::FindOptions::builder().sort(doc! { "index": 1 }).build()),
+            )
+            .await
+            .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+
+        let mut blocks = Vec::new();
+        while cursor.advance().await.map_err(|e| ApiError::DatabaseError(e.to_string()))? {
+            blocks.push(cursor.deserialize_current().map_err(|e| ApiError::DatabaseError(e.to_string()))?);
+        }
+        Ok(blocks)
+    }
+
+    // The most recent main-chain blocks, newest first
+    pub async fn get_recent_blocks(db: &mongodb::Database, count: i64) -> Result<Vec<Block>, ApiError> {
+        let options = mongodb::options::FindOptions::builder()
+            .sort(doc! { "index": -1 })
+            .limit(count)
+            .build();
+        let mut cursor = db
+            .collection::<Block>("blocks")
+            .find(doc! { "main_chain": { "$ne": false } }, options)
+            .await
+            .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+
+        let mut blocks = Vec::new();
+        while cursor.advance().await.map_err(|e| ApiError::DatabaseError(e.to_string()))? {
+            blocks.push(cursor.deserialize_current().map_err(|e| ApiError::DatabaseError(e.to_string()))?);
+        }
+        Ok(blocks)
+    }
+
+    pub async fn get_headers_from_index(
+        db: &mongodb::Database,
+        start_index: u64,
+        limit: i64,
+    ) -> Result<Vec<BlockHeader>, ApiError> {
+        let collection = db.collection::<BlockHeader>("blocks");
+        let options = mongodb::options::FindOptions::builder()
+            .sort(doc! { "index": 1 })
+            .projection(doc! { "transactions": 0 })
+            .limit(limit)
+            .build();
+        let mut cursor = collection
+            .find(doc! { "index": { "$gte": start_index as i64 }, "main_chain": { "$ne": false } }, options)
+            .await
+            .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+
+        let mut headers = Vec::new();
+        while cursor.advance().await.map_err(|e| ApiError::DatabaseError(e.to_string()))? {
+            headers.push(cursor.deserialize_current().map_err(|e| ApiError::DatabaseError(e.to_string()))?);
+        }
+        Ok(headers)
+    }
+
+    pub async fn get_main_chain_headers(db: &mongodb::Database) -> Result<Vec<BlockHeader>, ApiError> {
+        Self::get_headers_from_index(db, 0, i64::MAX).await
+    }
+
+    pub async fn get_blocks_by_hashes(db: &mongodb::Database, hashes: &[String]) -> Result<Vec<Block>, ApiError> {
+        let collection = db.collection::<Block>("blocks");
+        let mut cursor = collection
+            .find(doc! { "hash": { "$in": hashes } }, None)
             .await
             .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
 
@@ -142,14 +849,483 @@ impl Database {
         Ok(blocks)
     }
 
+    pub async fn save_sync_headers(db: &mongodb::Database, headers: &[BlockHeader]) -> Result<(), ApiError> {
+        let collection = db.collection::<BlockHeader>("sync_headers");
+        for header in headers {
+            collection
+                .replace_one(
+                    doc! { "hash": &header.hash },
+                    header,
+                    mongodb::options::ReplaceOptions::builder().upsert(true).build(),
+                )
+                .await
+                .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+        }
+        Ok(())
+    }
+
+    pub async fn get_sync_header(db: &mongodb::Database, hash: &str) -> Result<Option<BlockHeader>, ApiError> {
+        db.collection::<BlockHeader>("sync_headers")
+            .find_one(doc! { "hash": hash }, None)
+            .await
+            .map_err(|e| ApiError::DatabaseError(e.to_string()))
+    }
+
+    // Downloaded headers above the given height, lowest first, whose bodies are still to be fetched
+    pub async fn get_sync_headers_above(
+        db: &mongodb::Database,
+        height: u64,
+        limit: i64,
+    ) -> Result<Vec<BlockHeader>, ApiError> {
+        let collection = db.collection::<BlockHeader>("sync_headers");
+        let mut cursor = collection
+            .find(
+                doc! { "index": { "$gt": height as i64 } },
+                Some(mongodb::options::FindOptions::builder().sort(doc! { "index": 1 }).limit(limit).build()),
+            )
+            .await
+            .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+
+        let mut headers = Vec::new();
+        while cursor.advance().await.map_err(|e| ApiError::DatabaseError(e.to_string()))? {
+            headers.push(cursor.deserialize_current().map_err(|e| ApiError::DatabaseError(e.to_string()))?);
+        }
+        Ok(headers)
+    }
+
+    pub async fn clear_sync_headers(db: &mongodb::Database) -> Result<(), ApiError> {
+        db.collection::<BlockHeader>("sync_headers")
+            .delete_many(doc! {}, None)
+            .await
+            .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+        Ok(())
+    }
+
+    pub async fn get_sync_progress(db: &mongodb::Database) -> Result<Option<SyncProgress>, ApiError> {
+        db.collection::<SyncProgress>("sync_progress")
+            .find_one(doc! {}, None)
+            .await
+            .map_err(|e| ApiError::DatabaseError(e.to_string()))
+    }
+
+    pub async fn save_sync_progress(db: &mongodb::Database, progress: &SyncProgress) -> Result<(), ApiError> {
+        db.collection::<SyncProgress>("sync_progress")
+            .replace_one(
+                doc! {},
+                progress,
+                mongodb::options::ReplaceOptions::builder().upsert(true).build(),
+            )
+            .await
+            .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+        Ok(())
+    }
+
+    pub async fn get_scheduled_consolidations(db: &mongodb::Database) -> Result<Vec<ScheduledConsolidation>, ApiError> {
+        let mut cursor = db
+            .collection::<ScheduledConsolidation>("scheduled_consolidations")
+            .find(doc! {}, None)
+            .await
+            .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+
+        let mut schedules = Vec::new();
+        while cursor.advance().await.map_err(|e| ApiError::DatabaseError(e.to_string()))? {
+            schedules.push(cursor.deserialize_current().map_err(|e| ApiError::DatabaseError(e.to_string()))?);
+        }
+        Ok(schedules)
+    }
+
+    pub async fn save_scheduled_consolidation(
+        db: &mongodb::Database,
+        schedule: &ScheduledConsolidation,
+    ) -> Result<(), ApiError> {
+        db.collection::<ScheduledConsolidation>("scheduled_consolidations")
+            .replace_one(
+                doc! { "wallet_id": &schedule.wallet_id },
+                schedule,
+                mongodb::options::ReplaceOptions::builder().upsert(true).build(),
+            )
+            .await
+            .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+        Ok(())
+    }
+
+    // Returns whether a schedule existed
+    pub async fn delete_scheduled_consolidation(db: &mongodb::Database, wallet_id: &str) -> Result<bool, ApiError> {
+        let result = db
+            .collection::<ScheduledConsolidation>("scheduled_consolidations")
+            .delete_one(doc! { "wallet_id": wallet_id }, None)
+            .await
+            .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+        Ok(result.deleted_count > 0)
+    }
+
+    // Claims an idempotency key. Returns the existing record if the key is already taken.
+    pub async fn reserve_idempotency_key(
+        db: &mongodb::Database,
+        record: &IdempotencyRecord,
+    ) -> Result<Option<IdempotencyRecord>, ApiError> {
+        let collection = db.collection::<IdempotencyRecord>("idempotency_keys");
+        for _ in 0..IDEMPOTENCY_RESERVE_ATTEMPTS {
+            match collection.insert_one(record, None).await {
+                Ok(_) => return Ok(None),
+                Err(e) if Self::is_duplicate_key(&e) => {}
+                Err(e) => return Err(ApiError::DatabaseError(e.to_string())),
+            }
+
+            let existing = collection
+                .find_one(doc! { "_id": &record.id }, None)
+                .await
+                .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+            match existing {
+                // The TTL monitor only runs about once a minute, so an expired key may still be stored
+                Some(existing) if existing.expires_at < mongodb::bson::DateTime::now() => {
+                    collection
+                        .delete_one(doc! { "_id": &existing.id, "expires_at": existing.expires_at }, None)
+                        .await
+                        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+                }
+                Some(existing) => return Ok(Some(existing)),
+                // Released by a failed request between our insert and the lookup; claim it again
+                None => {}
+            }
+        }
+        Err(ApiError::Conflict(
+            "A request with this Idempotency-Key is still being processed".to_string(),
+        ))
+    }
+
+    pub async fn complete_idempotency_key(db: &mongodb::Database, id: &str, tx_hash: &str) -> Result<(), ApiError> {
+        let collection = db.collection::<IdempotencyRecord>("idempotency_keys");
+        let mut last_error = None;
+        for _ in 0..IDEMPOTENCY_COMPLETE_ATTEMPTS {
+            match collection
+                .update_one(doc! { "_id": id }, doc! { "$set": { "transaction_hash": tx_hash } }, None)
+                .await
+            {
+                Ok(_) => return Ok(()),
+                Err(e) => last_error = Some(e),
+            }
+        }
+        Err(ApiError::DatabaseError(last_error.map(|e| e.to_string()).unwrap_or_default()))
+    }
+
+    // Records the idempotency key on the transaction it created, so a retry can still find the
+    // transaction if the key's own record was never completed
+    pub async fn set_transaction_idempotency_key(
+        db: &mongodb::Database,
+        tx_hash: &str,
+        id: &str,
+    ) -> Result<(), ApiError> {
+        db.collection::<Transaction>("transactions")
+            .update_one(
+                doc! { "transaction_hash": tx_hash },
+                doc! { "$set": { "idempotency_key": id } },
+                None,
+            )
+            .await
+            .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+        Ok(())
+    }
+
+    pub async fn find_transaction_by_idempotency_key(
+        db: &mongodb::Database,
+        id: &str,
+    ) -> Result<Option<Transaction>, ApiError> {
+        db.collection::<Transaction>("transactions")
+            .find_one(doc! { "idempotency_key": id }, None)
+            .await
+            .map_err(|e| ApiError::DatabaseError(e.to_string()))
+    }
+
+    // Frees the key of a request that failed, so a retry runs it again
+    pub async fn release_idempotency_key(db: &mongodb::Database, id: &str) -> Result<(), ApiError> {
+        db.collection::<IdempotencyRecord>("idempotency_keys")
+            .delete_one(doc! { "_id": id }, None)
+            .await
+            .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+        Ok(())
+    }
+
+    fn is_duplicate_key(error: &mongodb::error::Error) -> bool {
+        matches!(
+            error.kind.as_ref(),
+            mongodb::error::ErrorKind::Write(mongodb::error::WriteFailure::WriteError(e)) if e.code == 11000
+        )
+    }
+
     pub async fn get_latest_block(db: &mongodb::Database) -> Result<Option<Block>, ApiError> {
         let collection = db.collection::<Block>("blocks");
         collection
-            .find_one(doc! {}, Some(mongodb::options::FindOneOptions::builder().sort(doc! { "index": -1 }).build()))
+            .find_one(
+                doc! { "main_chain": { "$ne": false } },
+                Some(mongodb::options::FindOneOptions::builder().sort(doc! { "index": -1 }).build()),
+            )
+            .await
+            .map_err(|e| ApiError::DatabaseError(e.to_string()))
+    }
+
+    // Outputs not created by any recorded transaction are funds that predate the chain
+    pub async fn get_initial_chain_state(
+        db: &mongodb::Database,
+        utxos: &HashMap<String, UTXO>,
+    ) -> Result<ChainState, ApiError> {
+        // Outputs of side-chain blocks are stored before their transactions and are still "pending";
+        // they are not allocations
+        let tx_hashes = Self::get_transaction_hashes(db).await?;
+        let allocations = utxos
+            .iter()
+            .filter(|(_, u)| !tx_hashes.contains(&u.transaction_hash) && u.block_hash != "pending")
+            .map(|(id, u)| (id.clone(), u.clone()))
+            .collect();
+        Ok(ChainState::new(allocations))
+    }
+
+    // Replays every stored block to rebuild the spendable output set at the tip
+    pub async fn load_chain_state(
+        db: &mongodb::Database,
+    ) -> Result<(ChainState, HashMap<String, UTXO>), ApiError> {
+        let blocks = Self::get_all_blocks(db).await?;
+        let utxos = Self::get_all_utxos(db).await?;
+        let mut state = Self::get_initial_chain_state(db, &utxos).await?;
+        for block in &blocks {
+            state.apply_block(block, &utxos);
+        }
+        Ok((state, utxos))
+    }
+
+    pub async fn get_block_by_index(db: &mongodb::Database, index: u64) -> Result<Option<Block>, ApiError> {
+        let collection = db.collection::<Block>("blocks");
+        collection
+            .find_one(doc! { "index": index as i64, "main_chain": { "$ne": false } }, None)
+            .await
+            .map_err(|e| ApiError::DatabaseError(e.to_string()))
+    }
+
+    pub async fn get_blockchain_state(db: &mongodb::Database) -> Result<Option<BlockchainState>, ApiError> {
+        let collection = db.collection::<BlockchainState>("blockchain_state");
+        collection
+            .find_one(doc! {}, None)
             .await
             .map_err(|e| ApiError::DatabaseError(e.to_string()))
     }
 
+    pub async fn save_blockchain_state(db: &mongodb::Database, state: &BlockchainState) -> Result<(), ApiError> {
+        db.collection::<BlockchainState>("blockchain_state")
+            .replace_one(
+                doc! {},
+                state,
+                mongodb::options::ReplaceOptions::builder().upsert(true).build(),
+            )
+            .await
+            .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+        Ok(())
+    }
+
+    // Derives the state document from the stored blocks and pending transactions
+    pub async fn build_blockchain_state(db: &mongodb::Database) -> Result<BlockchainState, ApiError> {
+        let latest_block = Self::get_latest_block(db)
+            .await?
+            .ok_or_else(|| ApiError::InternalError("Blockchain has no blocks".to_string()))?;
+        let chain_length = db
+            .collection::<Block>("blocks")
+            .count_documents(doc! { "main_chain": { "$ne": false } }, None)
+            .await
+            .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+        let pending = db
+            .collection::<Transaction>("transactions")
+            .distinct("transaction_hash", doc! { "status": { "$in": Self::UNCONFIRMED_STATUSES.to_vec() } }, None)
+            .await
+            .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+
+        Ok(BlockchainState {
+            id: None,
+            latest_block_hash: latest_block.hash,
+            chain_length,
+            pending_transactions: pending.into_iter().filter_map(|h| h.as_str().map(str::to_string)).collect(),
+            difficulty: latest_block.difficulty,
+            last_updated: Utc::now(),
+        })
+    }
+
+    // Inserts a block and moves the state tip to it in one compare-and-swap on the previous tip.
+    // If another block claimed the tip first, the insert is undone so the tip never skips a block.
+    pub async fn append_block(db: &mongodb::Database, block: &Block) -> Result<(), ApiError> {
+        Self::create_block(db, block).await?;
+
+        let mined: Vec<&str> = block.transactions.iter().map(|tx| tx.transaction_hash.as_str()).collect();
+        let now = mongodb::bson::to_bson(&Utc::now()).map_err(|e| ApiError::InternalError(e.to_string()))?;
+        let result = db
+            .collection::<BlockchainState>("blockchain_state")
+            .update_one(
+                doc! { "latest_block_hash": &block.previous_hash },
+                doc! {
+                    "$set": {
+                        "latest_block_hash": &block.hash,
+                        "chain_length": (block.index + 1) as i64,
+                        "difficulty": block.difficulty as i64,
+                        "last_updated": now,
+                    },
+                    "$pull": { "pending_transactions": { "$in": mined } },
+                },
+                None,
+            )
+            .await
+            .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+
+        if result.matched_count == 0 {
+            Self::delete_block(db, &block.hash).await?;
+            return Err(ApiError::BadRequest(
+                "Chain tip changed while the block was being mined; retry".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    pub async fn get_chain_tip(db: &mongodb::Database) -> Result<Block, ApiError> {
+        let state = Self::get_blockchain_state(db)
+            .await?
+            .ok_or_else(|| ApiError::InternalError("Blockchain state not initialized".to_string()))?;
+        Self::get_block_by_hash(db, &state.latest_block_hash)
+            .await?
+            .ok_or_else(|| ApiError::InternalError(format!("Tip block {} not found", state.latest_block_hash)))
+    }
+
+    // Rebuilds the state document from the blocks collection
+    pub async fn repair_blockchain_state(db: &mongodb::Database) -> Result<BlockchainState, ApiError> {
+        let state = Self::build_blockchain_state(db).await?;
+        Self::save_blockchain_state(db, &state).await?;
+        Ok(state)
+    }
+
+    pub async fn add_pending_transaction(db: &mongodb::Database, tx_hash: &str) -> Result<(), ApiError> {
+        db.collection::<BlockchainState>("blockchain_state")
+            .update_one(
+                doc! {},
+                doc! { "$addToSet": { "pending_transactions": tx_hash } },
+                None,
+            )
+            .await
+            .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+        Ok(())
+    }
+
+    pub async fn remove_pending_transactions(db: &mongodb::Database, tx_hashes: &[String]) -> Result<(), ApiError> {
+        db.collection::<BlockchainState>("blockchain_state")
+            .update_one(
+                doc! {},
+                doc! { "$pull": { "pending_transactions": { "$in": tx_hashes } } },
+                None,
+            )
+            .await
+            .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+        Ok(())
+    }
+
+    // Indexes the application relies on for correctness rather than speed
+    pub async fn create_indexes(db: &mongodb::Database) -> Result<(), ApiError> {
+        let expire_on_date = mongodb::IndexModel::builder()
+            .keys(doc! { "expires_at": 1 })
+            .options(
+                mongodb::options::IndexOptions::builder()
+                    .expire_after(std::time::Duration::ZERO)
+                    .build(),
+            )
+            .build();
+        db.collection::<IdempotencyRecord>("idempotency_keys")
+            .create_index(expire_on_date, None)
+            .await
+            .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+
+        // Only transactions created with an Idempotency-Key carry the field
+        let by_idempotency_key = mongodb::IndexModel::builder()
+            .keys(doc! { "idempotency_key": 1 })
+            .options(mongodb::options::IndexOptions::builder().sparse(true).build())
+            .build();
+        db.collection::<Transaction>("transactions")
+            .create_index(by_idempotency_key, None)
+            .await
+            .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+        Ok(())
+    }
+
+    // Writes the configured genesis block on first boot and refuses to start on a different one
+    pub async fn init_chain(db: &mongodb::Database) -> Result<(), ApiError> {
+        let genesis = Blockchain::create_genesis_block();
+        match Self::get_block_by_index(db, 0).await? {
+            Some(stored) if Blockchain::is_legacy_genesis(&stored) => {
+                log::warn!(
+                    "Keeping legacy genesis block {}; GENESIS_TIMESTAMP does not apply to this database",
+                    stored.hash
+                );
+                Blockchain::adopt_legacy_genesis(&stored);
+            }
+            Some(stored) => {
+                if !Blockchain::is_valid_genesis(&stored) {
+                    return Err(ApiError::InternalError(format!(
+                        "Stored genesis block {} does not match configured genesis {}; set GENESIS_TIMESTAMP \
+                         to the network's value or reset the database",
+                        stored.hash, genesis.hash
+                    )));
+                }
+            }
+            None => {
+                Self::create_block(db, &genesis).await?;
+                log::info!("Created genesis block {}", genesis.hash);
+            }
+        }
+
+        Self::backfill_chain_work(db).await?;
+
+        if Self::get_blockchain_state(db).await?.is_none() {
+            let state = Self::build_blockchain_state(db).await?;
+            Self::save_blockchain_state(db, &state).await?;
+        }
+        Ok(())
+    }
+
+    // Blocks stored before fork handling have no cumulative work; derive it along the main chain
+    async fn backfill_chain_work(db: &mongodb::Database) -> Result<(), ApiError> {
+        let missing = db
+            .collection::<Block>("blocks")
+            .count_documents(doc! { "chain_work": { "$in": [0_i64, mongodb::bson::Bson::Null] } }, None)
+            .await
+            .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+        if missing == 0 {
+            return Ok(());
+        }
+
+        let mut chain_work = 0u64;
+        for block in Self::get_all_blocks(db).await? {
+            chain_work += Blockchain::block_work(&block);
+            Self::set_block_chain_work(db, &block.hash, chain_work).await?;
+        }
+        Ok(())
+    }
+
+    pub async fn set_block_chain_work(db: &mongodb::Database, hash: &str, chain_work: u64) -> Result<(), ApiError> {
+        db.collection::<Block>("blocks")
+            .update_one(doc! { "hash": hash }, doc! { "$set": { "chain_work": chain_work as i64 } }, None)
+            .await
+            .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+        Ok(())
+    }
+
+    pub async fn set_block_main_chain(db: &mongodb::Database, hash: &str, main_chain: bool) -> Result<(), ApiError> {
+        db.collection::<Block>("blocks")
+            .update_one(doc! { "hash": hash }, doc! { "$set": { "main_chain": main_chain } }, None)
+            .await
+            .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+        Ok(())
+    }
+
+    pub async fn delete_block(db: &mongodb::Database, hash: &str) -> Result<(), ApiError> {
+        db.collection::<Block>("blocks")
+            .delete_one(doc! { "hash": hash }, None)
+            .await
+            .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+        Ok(())
+    }
+
     pub async fn get_block_by_hash(db: &mongodb::Database, hash: &str) -> Result<Option<Block>, ApiError> {
         let collection = db.collection::<Block>("blocks");
         collection
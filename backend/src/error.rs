@@ -7,6 +7,8 @@ pub enum ApiError {
     NotFound(String),
     BadRequest(String),
     Unauthorized(String),
+    Forbidden(String),
+    Conflict(String),
     InternalError(String),
     InvalidSignature,
     InvalidWalletId,
@@ -20,6 +22,8 @@ impl fmt::Display for ApiError {
             ApiError::NotFound(msg) => write!(f, "Not found: {}", msg),
             ApiError::BadRequest(msg) => write!(f, "Bad request: {}", msg),
             ApiError::Unauthorized(msg) => write!(f, "Unauthorized: {}", msg),
+            ApiError::Forbidden(msg) => write!(f, "Forbidden: {}", msg),
+            ApiError::Conflict(msg) => write!(f, "Conflict: {}", msg),
             ApiError::InternalError(msg) => write!(f, "Internal error: {}", msg),
             ApiError::InvalidSignature => write!(f, "Invalid signature"),
             ApiError::InvalidWalletId => write!(f, "Invalid wallet ID"),
@@ -35,6 +39,8 @@ impl ResponseError for ApiError {
             ApiError::NotFound(msg) => (StatusCode::NOT_FOUND, msg.clone()),
             ApiError::BadRequest(msg) => (StatusCode::BAD_REQUEST, msg.clone()),
             ApiError::Unauthorized(msg) => (StatusCode::UNAUTHORIZED, msg.clone()),
+            ApiError::Forbidden(msg) => (StatusCode::FORBIDDEN, msg.clone()),
+            ApiError::Conflict(msg) => (StatusCode::CONFLICT, msg.clone()),
             ApiError::InternalError(msg) => (StatusCode::INTERNAL_SERVER_ERROR, msg.clone()),
             ApiError::InvalidSignature => (StatusCode::BAD_REQUEST, "Invalid signature".to_string()),
             ApiError::InvalidWalletId => (StatusCode::BAD_REQUEST, "Invalid wallet ID".to_string()),
@@ -52,6 +58,8 @@ impl ResponseError for ApiError {
             ApiError::NotFound(_) => StatusCode::NOT_FOUND,
             ApiError::BadRequest(_) => StatusCode::BAD_REQUEST,
             ApiError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            ApiError::Forbidden(_) => StatusCode::FORBIDDEN,
+            ApiError::Conflict(_) => StatusCode::CONFLICT,
             ApiError::InternalError(_) => StatusCode::INTERNAL_SERVER_ERROR,
             ApiError::InvalidSignature => StatusCode::BAD_REQUEST,
             ApiError::InvalidWalletId => StatusCode::BAD_REQUEST,
@@ -60,3 +68,96 @@ impl ResponseError for ApiError {
         }
     }
 }
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ValidationError {
+    InsufficientWork,
+    UnexpectedDifficulty(u32),
+    UnsupportedVersion(u32),
+    MutatedMerkleTree,
+    PreviousHashMismatch { expected: String, found: String },
+    MerkleRootMismatch,
+    HashMismatch,
+    BlockTooLarge { size: usize, max: usize },
+    TimestampTooEarly,
+    TimestampTooFarInFuture,
+    DuplicateTransaction(String),
+    InvalidSignature(String),
+    TransactionHashMismatch(String),
+    NoInputs(String),
+    UnknownInput { tx: String, utxo: String },
+    InputNotOwned { tx: String, utxo: String },
+    DoubleSpend { tx: String, utxo: String },
+    InvalidOutput(String),
+    FeeMismatch { tx: String, declared: f64, actual: f64 },
+    InvalidCoinbase(String),
+    InsufficientInputs { tx: String, inputs: f64, outputs: f64 },
+    WrongChain { tx: String, chain_id: String },
+    NonceReused { tx: String, nonce: u64, last: u64 },
+    MissingReplayProtection(String),
+    MissingOutputs(String),
+    InvalidTransactionHash(String),
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ValidationError::InsufficientWork => write!(f, "Block hash does not meet the difficulty target"),
+            ValidationError::UnexpectedDifficulty(d) => write!(f, "Unexpected block difficulty {}", d),
+            ValidationError::UnsupportedVersion(v) => write!(f, "Unsupported block version {}", v),
+            ValidationError::MutatedMerkleTree => write!(f, "Block repeats a transaction in its merkle tree"),
+            ValidationError::PreviousHashMismatch { expected, found } => {
+                write!(f, "Previous hash mismatch: expected {}, found {}", expected, found)
+            }
+            ValidationError::MerkleRootMismatch => write!(f, "Merkle root does not match transactions"),
+            ValidationError::HashMismatch => write!(f, "Block hash does not match header"),
+            ValidationError::BlockTooLarge { size, max } => {
+                write!(f, "Block size {} exceeds limit of {} bytes", size, max)
+            }
+            ValidationError::TimestampTooEarly => write!(f, "Block timestamp is not after median time past"),
+            ValidationError::TimestampTooFarInFuture => write!(f, "Block timestamp is too far in the future"),
+            ValidationError::DuplicateTransaction(tx) => write!(f, "Duplicate transaction {}", tx),
+            ValidationError::InvalidSignature(tx) => write!(f, "Invalid signature on transaction {}", tx),
+            ValidationError::TransactionHashMismatch(tx) => {
+                write!(f, "Transaction hash {} does not match the transaction's contents", tx)
+            }
+            ValidationError::NoInputs(tx) => write!(f, "Transaction {} has no inputs", tx),
+            ValidationError::UnknownInput { tx, utxo } => {
+                write!(f, "Transaction {} spends missing or spent output {}", tx, utxo)
+            }
+            ValidationError::InputNotOwned { tx, utxo } => {
+                write!(f, "Transaction {} spends output {} not owned by sender", tx, utxo)
+            }
+            ValidationError::DoubleSpend { tx, utxo } => {
+                write!(f, "Transaction {} double spends output {}", tx, utxo)
+            }
+            ValidationError::InvalidOutput(tx) => write!(f, "Transaction {} has invalid outputs", tx),
+            ValidationError::FeeMismatch { tx, declared, actual } => {
+                write!(f, "Transaction {} declares fee {} but pays {}", tx, declared, actual)
+            }
+            ValidationError::InvalidCoinbase(tx) => write!(f, "Invalid coinbase transaction {}", tx),
+            ValidationError::InsufficientInputs { tx, inputs, outputs } => {
+                write!(f, "Transaction {} inputs {} do not cover outputs {}", tx, inputs, outputs)
+            }
+            ValidationError::WrongChain { tx, chain_id } => {
+                write!(f, "Transaction {} is signed for chain {}", tx, chain_id)
+            }
+            ValidationError::NonceReused { tx, nonce, last } => {
+                write!(f, "Transaction {} reuses nonce {}; the sender has already used {}", tx, nonce, last)
+            }
+            ValidationError::MissingReplayProtection(tx) => {
+                write!(f, "Transaction {} does not carry a chain ID and nonce", tx)
+            }
+            ValidationError::MissingOutputs(tx) => write!(f, "Transaction {} does not list its outputs", tx),
+            ValidationError::InvalidTransactionHash(tx) => {
+                write!(f, "Transaction hash '{}' is not a hex SHA-256 digest", tx)
+            }
+        }
+    }
+}
+
+impl From<ValidationError> for ApiError {
+    fn from(err: ValidationError) -> Self {
+        ApiError::BadRequest(err.to_string())
+    }
+}
@@ -1,10 +1,11 @@
 use actix_web::{web, HttpResponse, HttpRequest};
 use mongodb::Database;
 use serde_json::json;
-use crate::models::{RegisterRequest, LoginRequest, VerifyOtpRequest, User, UserResponse, AuthResponse};
-use crate::crypto::{generate_keypair, generate_wallet_id, hash_password, generate_otp};
+use crate::models::{RegisterRequest, LoginRequest, VerifyOtpRequest, RestoreWalletRequest, User, UserResponse, AuthResponse};
+use crate::crypto::{generate_mnemonic, keypair_from_mnemonic, generate_wallet_id, hash_password, generate_otp};
 use crate::db::Database as DbOps;
 use crate::error::ApiError;
+use crate::address;
 use chrono::Utc;
 use std::collections::HashMap;
 use std::sync::Mutex;
@@ -23,7 +24,10 @@ pub async fn register(
         return Err(ApiError::BadRequest("User already exists".to_string()));
     }
 
-    let (private_key, public_key) = generate_keypair();
+    // The keypair comes from a recovery phrase that is returned once and never stored
+    let mnemonic = generate_mnemonic();
+    let (private_key, public_key) = keypair_from_mnemonic(&mnemonic, req.passphrase.as_deref().unwrap_or(""))
+        .ok_or_else(|| ApiError::InternalError("Failed to derive keypair".to_string()))?;
     let wallet_id = generate_wallet_id(&public_key);
 
     let user = User {
@@ -49,9 +53,70 @@ pub async fn register(
         user: UserResponse {
             email: user.email,
             full_name: user.full_name,
+            address: address::encode(&user.wallet_id),
             wallet_id: user.wallet_id,
             public_key: user.public_key,
         },
+        mnemonic: Some(mnemonic),
+    }))
+}
+
+// Re-derives a wallet's keypair from its recovery phrase and links the wallet to the given account
+// details. An account still holding the wallet is taken over; otherwise a new one is created.
+pub async fn restore(
+    db: web::Data<Database>,
+    req: web::Json<RestoreWalletRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let (private_key, public_key) = keypair_from_mnemonic(&req.mnemonic, req.passphrase.as_deref().unwrap_or(""))
+        .ok_or_else(|| ApiError::BadRequest("Invalid recovery phrase".to_string()))?;
+    let wallet_id = generate_wallet_id(&public_key);
+
+    if let Some(other) = DbOps::find_user_by_email(&db, &req.email).await? {
+        if other.wallet_id != wallet_id {
+            return Err(ApiError::BadRequest("User already exists".to_string()));
+        }
+    }
+
+    let user = match DbOps::find_user_by_wallet_id(&db, &wallet_id).await? {
+        Some(mut user) => {
+            user.email = req.email.clone();
+            user.full_name = req.full_name.clone();
+            user.cnic = req.cnic.clone();
+            user.password_hash = hash_password(&req.password);
+            DbOps::relink_user(&db, &user).await?;
+            user
+        }
+        None => {
+            let user = User {
+                id: None,
+                email: req.email.clone(),
+                full_name: req.full_name.clone(),
+                cnic: req.cnic.clone(),
+                password_hash: hash_password(&req.password),
+                wallet_id: wallet_id.clone(),
+                public_key,
+                private_key_encrypted: private_key,
+                created_at: Utc::now(),
+                beneficiaries: Vec::new(),
+                zakat_deduction: 0.0,
+            };
+            DbOps::create_user(&db, &user).await?;
+            user
+        }
+    };
+
+    let token = format!("token_{}", wallet_id);
+
+    Ok(HttpResponse::Ok().json(AuthResponse {
+        token,
+        user: UserResponse {
+            email: user.email,
+            full_name: user.full_name,
+            address: address::encode(&user.wallet_id),
+            wallet_id: user.wallet_id,
+            public_key: user.public_key,
+        },
+        mnemonic: None,
     }))
 }
 
@@ -101,8 +166,10 @@ pub async fn verify_otp(
         user: UserResponse {
             email: user.email,
             full_name: user.full_name,
+            address: address::encode(&user.wallet_id),
             wallet_id: user.wallet_id,
             public_key: user.public_key,
         },
+        mnemonic: None,
     }))
 }
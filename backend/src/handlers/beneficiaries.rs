@@ -0,0 +1,203 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use chrono::{DateTime, Utc};
+use mongodb::Database;
+use serde_json::json;
+use std::collections::HashMap;
+use crate::models::{
+    AddBeneficiaryRequest, Beneficiary, Payment, RenameBeneficiaryRequest, SendMoneyRequest, SendToBeneficiaryRequest, User,
+};
+use crate::db::Database as DbOps;
+use crate::error::ApiError;
+use crate::address;
+use crate::handlers::transactions::send;
+use crate::mempool::Mempool;
+use crate::p2p::PeerManager;
+
+pub const DEFAULT_BENEFICIARY_COOLING_OFF_HOURS: i64 = 24;
+const MAX_NICKNAME_LENGTH: usize = 50;
+
+// Payments of at least this much to one wallet may only go to beneficiaries saved for longer than
+// the cooling-off period. Unset disables the policy.
+pub fn large_transfer_threshold() -> Option<f64> {
+    std::env::var("LARGE_TRANSFER_THRESHOLD").ok().and_then(|v| v.parse().ok())
+}
+
+pub fn beneficiary_cooling_off_hours() -> i64 {
+    std::env::var("BENEFICIARY_COOLING_OFF_HOURS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_BENEFICIARY_COOLING_OFF_HOURS)
+}
+
+// When a beneficiary may first receive large transfers, or None while the policy is off
+fn large_transfers_allowed_from(beneficiary: &Beneficiary) -> Option<DateTime<Utc>> {
+    large_transfer_threshold()
+        .map(|_| beneficiary.created_at + chrono::Duration::hours(beneficiary_cooling_off_hours()))
+}
+
+// Rejects payments that send a large amount to a wallet that is not an established beneficiary
+pub fn check_large_transfer_policy(sender: &User, payments: &[Payment]) -> Result<(), ApiError> {
+    let Some(threshold) = large_transfer_threshold() else {
+        return Ok(());
+    };
+    let mut per_recipient: HashMap<&str, f64> = HashMap::new();
+    for payment in payments {
+        *per_recipient.entry(payment.recipient_wallet_id.as_str()).or_default() += payment.amount;
+    }
+
+    let now = Utc::now();
+    for (recipient, amount) in per_recipient {
+        if amount < threshold || recipient == sender.wallet_id {
+            continue;
+        }
+        let established = sender
+            .beneficiaries
+            .iter()
+            .find(|b| b.wallet_id == recipient)
+            .and_then(large_transfers_allowed_from)
+            .is_some_and(|allowed_from| allowed_from <= now);
+        if !established {
+            return Err(ApiError::Forbidden(format!(
+                "Transfers of {} or more may only go to beneficiaries saved at least {} hours ago",
+                threshold,
+                beneficiary_cooling_off_hours()
+            )));
+        }
+    }
+    Ok(())
+}
+
+pub async fn list_beneficiaries(
+    db: web::Data<Database>,
+    wallet_id: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    let user = find_user(&db, &address::parse(&wallet_id)?).await?;
+    let beneficiaries: Vec<_> = user.beneficiaries.iter().map(beneficiary_json).collect();
+    Ok(HttpResponse::Ok().json(beneficiaries))
+}
+
+pub async fn add_beneficiary(
+    db: web::Data<Database>,
+    wallet_id: web::Path<String>,
+    req: web::Json<AddBeneficiaryRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let user = find_user(&db, &address::parse(&wallet_id)?).await?;
+    let beneficiary_wallet_id = req.wallet_id.as_str();
+    if beneficiary_wallet_id == user.wallet_id {
+        return Err(ApiError::BadRequest("A wallet cannot be its own beneficiary".to_string()));
+    }
+    // Either an account on this node or an address the ledger has paid before
+    if DbOps::find_user_by_wallet_id(&db, beneficiary_wallet_id).await?.is_none()
+        && !DbOps::wallet_has_utxos(&db, beneficiary_wallet_id).await?
+    {
+        return Err(ApiError::InvalidWalletId);
+    }
+    let nickname = validate_nickname(&user, &req.nickname, None)?;
+
+    let beneficiary = Beneficiary {
+        wallet_id: beneficiary_wallet_id.to_string(),
+        nickname,
+        created_at: Utc::now(),
+    };
+    if !DbOps::add_beneficiary(&db, &user.wallet_id, &beneficiary).await? {
+        return Err(ApiError::Conflict("This wallet is already a beneficiary".to_string()));
+    }
+    Ok(HttpResponse::Created().json(beneficiary_json(&beneficiary)))
+}
+
+pub async fn rename_beneficiary(
+    db: web::Data<Database>,
+    path: web::Path<(String, String)>,
+    req: web::Json<RenameBeneficiaryRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let (wallet_id, beneficiary_wallet_id) = path.into_inner();
+    let beneficiary_wallet_id = address::parse(&beneficiary_wallet_id)?;
+    let user = find_user(&db, &address::parse(&wallet_id)?).await?;
+    let nickname = validate_nickname(&user, &req.nickname, Some(&beneficiary_wallet_id))?;
+
+    if !DbOps::rename_beneficiary(&db, &user.wallet_id, &beneficiary_wallet_id, &nickname).await? {
+        return Err(ApiError::NotFound("Beneficiary not found".to_string()));
+    }
+    Ok(HttpResponse::Ok().json(json!({
+        "wallet_id": beneficiary_wallet_id,
+        "nickname": nickname,
+    })))
+}
+
+pub async fn remove_beneficiary(
+    db: web::Data<Database>,
+    path: web::Path<(String, String)>,
+) -> Result<HttpResponse, ApiError> {
+    let (wallet_id, beneficiary_wallet_id) = path.into_inner();
+    let beneficiary_wallet_id = address::parse(&beneficiary_wallet_id)?;
+    let user = find_user(&db, &address::parse(&wallet_id)?).await?;
+    if !DbOps::remove_beneficiary(&db, &user.wallet_id, &beneficiary_wallet_id).await? {
+        return Err(ApiError::NotFound("Beneficiary not found".to_string()));
+    }
+    Ok(HttpResponse::NoContent().finish())
+}
+
+// Pays a saved beneficiary, looked up by nickname (case-insensitive) or wallet ID
+pub async fn send_to_beneficiary(
+    db: web::Data<Database>,
+    mempool: web::Data<Mempool>,
+    peers: web::Data<PeerManager>,
+    http_req: HttpRequest,
+    req: web::Json<SendToBeneficiaryRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let req = req.into_inner();
+    let user = find_user(&db, &req.sender_wallet_id).await?;
+    let wanted = req.beneficiary.trim();
+    let wanted_wallet_id = address::parse(wanted).ok();
+    let beneficiary = user
+        .beneficiaries
+        .iter()
+        .find(|b| b.nickname.eq_ignore_ascii_case(wanted))
+        .or_else(|| user.beneficiaries.iter().find(|b| Some(&b.wallet_id) == wanted_wallet_id.as_ref()))
+        .ok_or_else(|| ApiError::NotFound("Beneficiary not found".to_string()))?;
+
+    let payment = SendMoneyRequest {
+        sender_wallet_id: req.sender_wallet_id,
+        recipient_wallet_id: beneficiary.wallet_id.clone(),
+        amount: req.amount,
+        fee: req.fee,
+        note: req.note,
+        coin_selection: req.coin_selection,
+    };
+    send(db, mempool, peers, &http_req, payment).await
+}
+
+async fn find_user(db: &Database, wallet_id: &str) -> Result<User, ApiError> {
+    DbOps::find_user_by_wallet_id(db, wallet_id)
+        .await?
+        .ok_or(ApiError::InvalidWalletId)
+}
+
+// Trims the nickname and checks it is unique among the user's other beneficiaries
+fn validate_nickname(user: &User, nickname: &str, renaming: Option<&str>) -> Result<String, ApiError> {
+    let nickname = nickname.trim();
+    if nickname.is_empty() || nickname.chars().count() > MAX_NICKNAME_LENGTH {
+        return Err(ApiError::BadRequest(format!(
+            "Nickname must be 1 to {} characters",
+            MAX_NICKNAME_LENGTH
+        )));
+    }
+    let taken = user
+        .beneficiaries
+        .iter()
+        .any(|b| Some(b.wallet_id.as_str()) != renaming && b.nickname.eq_ignore_ascii_case(nickname));
+    if taken {
+        return Err(ApiError::Conflict(format!("Nickname '{}' is already in use", nickname)));
+    }
+    Ok(nickname.to_string())
+}
+
+fn beneficiary_json(beneficiary: &Beneficiary) -> serde_json::Value {
+    json!({
+        "wallet_id": beneficiary.wallet_id,
+        "address": address::encode(&beneficiary.wallet_id),
+        "nickname": beneficiary.nickname,
+        "created_at": beneficiary.created_at,
+        "large_transfers_allowed_from": large_transfers_allowed_from(beneficiary),
+    })
+}
@@ -2,9 +2,12 @@ use actix_web::{web, HttpResponse};
 use mongodb::Database;
 use serde_json::json;
 use crate::db::Database as DbOps;
-use crate::blockchain::Blockchain;
+use crate::blockchain::{Blockchain, MAX_BLOCK_SIZE};
+use crate::chain::{BlockOutcome, ChainManager};
+use crate::p2p::{PeerManager, MAX_HEADERS_PER_MESSAGE};
 use crate::error::ApiError;
-use crate::models::Transaction;
+use crate::mempool::Mempool;
+use crate::models::{Block, HeadersQuery, MerkleProofResponse, MineRequest, Transaction, TransactionStatus, VerifyProofRequest};
 
 pub async fn get_blocks(
     db: web::Data<Database>,
@@ -13,21 +16,29 @@ pub async fn get_blocks(
     Ok(HttpResponse::Ok().json(blocks))
 }
 
+// Announces blocks that changed our main chain to the other nodes
+fn announce_block(db: web::Data<Database>, peers: web::Data<PeerManager>, block: Block) {
+    actix_web::rt::spawn(async move {
+        peers.broadcast_block(&db, &block).await;
+    });
+}
+
 pub async fn mine_block(
     db: web::Data<Database>,
+    mempool: web::Data<Mempool>,
+    peers: web::Data<PeerManager>,
 ) -> Result<HttpResponse, ApiError> {
-    let latest_block = DbOps::get_latest_block(&db)
-        .await?
-        .unwrap_or_else(|| Blockchain::create_genesis_block());
+    let latest_block = DbOps::get_chain_tip(&db).await?;
 
     let new_block = Blockchain::mine_block(
         latest_block.index + 1,
         Vec::new(),
         latest_block.hash.clone(),
         latest_block.difficulty,
-    );
+    )?;
 
-    DbOps::create_block(&db, &new_block).await?;
+    ChainManager::process_block(&db, &mempool, new_block.clone(), Vec::new()).await?;
+    announce_block(db.clone(), peers, new_block.clone());
 
     Ok(HttpResponse::Created().json(json!({
         "block_hash": new_block.hash,
@@ -36,15 +47,105 @@ pub async fn mine_block(
     })))
 }
 
+// Main-chain headers without transaction bodies
+pub async fn get_headers(
+    db: web::Data<Database>,
+    query: web::Query<HeadersQuery>,
+) -> Result<HttpResponse, ApiError> {
+    let count = query.count.unwrap_or(MAX_HEADERS_PER_MESSAGE).clamp(1, MAX_HEADERS_PER_MESSAGE);
+    let headers = DbOps::get_headers_from_index(&db, query.from.unwrap_or(0), count).await?;
+    Ok(HttpResponse::Ok().json(headers))
+}
+
+pub async fn get_header(
+    db: web::Data<Database>,
+    block_hash: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    let block = DbOps::get_block_by_hash(&db, &block_hash.into_inner())
+        .await?
+        .ok_or_else(|| ApiError::NotFound("Block not found".to_string()))?;
+    Ok(HttpResponse::Ok().json(json!({
+        "header": Blockchain::block_header(&block),
+        "main_chain": block.main_chain
+    })))
+}
+
+// Merkle branch proving a confirmed transaction is included in its main-chain block
+pub async fn get_merkle_proof(
+    db: web::Data<Database>,
+    tx_hash: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    let tx_hash = tx_hash.into_inner();
+    let tx = DbOps::get_transaction_by_hash(&db, &tx_hash)
+        .await?
+        .ok_or_else(|| ApiError::NotFound("Transaction not found".to_string()))?;
+    let block_hash = tx
+        .block_hash
+        .filter(|_| tx.status == TransactionStatus::Confirmed)
+        .ok_or_else(|| ApiError::BadRequest("Transaction is not confirmed".to_string()))?;
+    let block = DbOps::get_block_by_hash(&db, &block_hash)
+        .await?
+        .filter(|b| b.main_chain)
+        .ok_or_else(|| ApiError::NotFound("Block not found on the main chain".to_string()))?;
+
+    let branch = Blockchain::merkle_branch(&block.transactions, &tx_hash, block.version)
+        .ok_or_else(|| ApiError::InternalError("Transaction missing from its block".to_string()))?;
+
+    Ok(HttpResponse::Ok().json(MerkleProofResponse {
+        transaction_hash: tx_hash,
+        block_hash: block.hash,
+        block_index: block.index,
+        block_version: block.version,
+        merkle_root: block.merkle_root,
+        branch,
+    }))
+}
+
+pub async fn verify_merkle_proof(
+    req: web::Json<VerifyProofRequest>,
+) -> Result<HttpResponse, ApiError> {
+    // Sibling hashes are hex SHA-256 digests; anything else cannot be folded into a root
+    if let Some(step) = req
+        .branch
+        .iter()
+        .find(|step| step.hash.len() != 64 || !step.hash.chars().all(|c| c.is_ascii_hexdigit()))
+    {
+        return Err(ApiError::BadRequest(format!("Invalid branch hash '{}'", step.hash)));
+    }
+
+    let valid = Blockchain::verify_merkle_proof(
+        &req.transaction_hash,
+        &req.branch,
+        &req.merkle_root,
+        req.block_version,
+    );
+    Ok(HttpResponse::Ok().json(json!({ "valid": valid })))
+}
+
+// Accepts a block mined elsewhere; it extends the main chain, is stored as a side chain or triggers a reorg
+pub async fn submit_block(
+    db: web::Data<Database>,
+    mempool: web::Data<Mempool>,
+    peers: web::Data<PeerManager>,
+    block: web::Json<Block>,
+) -> Result<HttpResponse, ApiError> {
+    let block = block.into_inner();
+    let outcome = ChainManager::process_block(&db, &mempool, block.clone(), Vec::new()).await?;
+    if matches!(outcome, BlockOutcome::Extended | BlockOutcome::Reorganized { .. }) {
+        announce_block(db.clone(), peers, block);
+    }
+    Ok(HttpResponse::Ok().json(outcome))
+}
+
 pub async fn get_status(
     db: web::Data<Database>,
 ) -> Result<HttpResponse, ApiError> {
-    let latest_block = DbOps::get_latest_block(&db).await?;
-    
+    let state = DbOps::get_blockchain_state(&db).await?;
+
     Ok(HttpResponse::Ok().json(json!({
-        "latest_block": latest_block.map(|b| json!({
-            "hash": b.hash,
-            "index": b.index
+        "latest_block": state.map(|s| json!({
+            "hash": s.latest_block_hash,
+            "index": s.chain_length.saturating_sub(1)
         })),
         "status": "running"
     })))
@@ -52,57 +153,59 @@ pub async fn get_status(
 
 pub async fn mine_pending_transactions(
     db: web::Data<Database>,
+    mempool: web::Data<Mempool>,
+    peers: web::Data<PeerManager>,
+    req: Option<web::Json<MineRequest>>,
 ) -> Result<HttpResponse, ApiError> {
-    let collection = db.collection::<Transaction>("transactions");
-    let mut cursor = collection
-        .find(mongodb::bson::doc! { "status": "pending" }, None)
-        .await
-        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+    mempool.expire(&db).await?;
 
-    let mut pending_txs = Vec::new();
-    while cursor.advance().await.map_err(|e| ApiError::DatabaseError(e.to_string()))? {
-        pending_txs.push(cursor.deserialize_current().map_err(|e| ApiError::DatabaseError(e.to_string()))?);
-    }
+    // Highest fee rate first, parents ahead of their children
+    let pending_txs = mempool.select_for_block(MAX_BLOCK_SIZE);
+
+    let latest_block = DbOps::get_chain_tip(&db).await?;
+    let (state, utxos) = DbOps::load_chain_state(&db).await?;
+
+    // Only transactions that are valid against the current chain state are mined
+    let pending_txs = Blockchain::select_valid_transactions(pending_txs, &state, &utxos);
 
     if pending_txs.is_empty() {
         return Err(ApiError::BadRequest("No pending transactions to mine".to_string()));
     }
 
-    let latest_block = DbOps::get_latest_block(&db)
-        .await?
-        .unwrap_or_else(|| Blockchain::create_genesis_block());
+    // The miner collects the fees through a coinbase transaction at the start of the block
+    let fees: f64 = pending_txs.iter().map(|tx| tx.fee).sum();
+    let miner_wallet_id = req
+        .and_then(|r| r.into_inner().miner_wallet_id)
+        .or_else(|| std::env::var("MINER_WALLET_ID").ok());
+    let transactions_mined = pending_txs.len();
+    let mut block_txs = Vec::new();
+    let mut block_outputs = Vec::new();
+    let mut reward = 0.0;
+    if let Some(miner) = miner_wallet_id.filter(|_| fees > 0.0) {
+        let (coinbase, output) = Blockchain::create_coinbase(latest_block.index + 1, &latest_block.hash, &miner, fees);
+        block_txs.push(coinbase);
+        block_outputs.push(output);
+        reward = fees;
+    }
+    block_txs.extend(pending_txs);
 
     let new_block = Blockchain::mine_block(
         latest_block.index + 1,
-        pending_txs.clone(),
+        block_txs,
         latest_block.hash.clone(),
         latest_block.difficulty,
-    );
+    )?;
 
-    let tx_collection = db.collection::<Transaction>("transactions");
-    for tx in &pending_txs {
-        tx_collection
-            .update_one(
-                mongodb::bson::doc! { "transaction_hash": &tx.transaction_hash },
-                mongodb::bson::doc! {
-                    "$set": {
-                        "status": "confirmed",
-                        "block_hash": &new_block.hash
-                    }
-                },
-                None,
-            )
-            .await
-            .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
-    }
-
-    DbOps::create_block(&db, &new_block).await?;
+    ChainManager::process_block(&db, &mempool, new_block.clone(), block_outputs).await?;
+    announce_block(db.clone(), peers, new_block.clone());
 
     Ok(HttpResponse::Created().json(json!({
         "block_hash": new_block.hash,
         "index": new_block.index,
         "nonce": new_block.nonce,
-        "transactions_mined": pending_txs.len()
+        "transactions_mined": transactions_mined,
+        "fees": fees,
+        "reward": reward
     })))
 }
 
@@ -118,18 +221,28 @@ pub async fn validate_blockchain(
         })));
     }
 
+    if !Blockchain::is_valid_genesis(&blocks[0]) {
+        return Ok(HttpResponse::Ok().json(json!({
+            "valid": false,
+            "total_blocks": blocks.len(),
+            "error": "Genesis block does not match configured genesis"
+        })));
+    }
+
+    let utxos = DbOps::get_all_utxos(&db).await?;
+    let mut state = DbOps::get_initial_chain_state(&db, &utxos).await?;
+    state.apply_block(&blocks[0], &utxos);
+
     let mut is_valid = true;
     let mut error_message = String::new();
 
-    for i in 1..blocks.len() {
-        let current = &blocks[i];
-        let previous = &blocks[i - 1];
-
-        if !Blockchain::validate_block(current, &previous.hash, previous.difficulty) {
+    for (i, current) in blocks.iter().enumerate().skip(1) {
+        if let Err(e) = Blockchain::validate_block(current, &state, &utxos) {
             is_valid = false;
-            error_message = format!("Block {} is invalid", i);
+            error_message = format!("Block {} is invalid: {}", i, e);
             break;
         }
+        state.apply_block(current, &utxos);
     }
 
     Ok(HttpResponse::Ok().json(json!({
@@ -142,7 +255,9 @@ pub async fn validate_blockchain(
 pub async fn get_chain_info(
     db: web::Data<Database>,
 ) -> Result<HttpResponse, ApiError> {
-    let blocks = DbOps::get_all_blocks(&db).await?;
+    let state = DbOps::get_blockchain_state(&db)
+        .await?
+        .ok_or_else(|| ApiError::InternalError("Blockchain state not initialized".to_string()))?;
     let tx_collection = db.collection::<Transaction>("transactions");
     let total_transactions = tx_collection
         .count_documents(mongodb::bson::doc! {}, None)
@@ -150,9 +265,11 @@ pub async fn get_chain_info(
         .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
 
     Ok(HttpResponse::Ok().json(json!({
-        "chain_length": blocks.len(),
+        "chain_length": state.chain_length,
         "total_transactions": total_transactions,
-        "latest_block_hash": blocks.last().map(|b| &b.hash),
-        "difficulty": blocks.last().map(|b| b.difficulty)
+        "latest_block_hash": state.latest_block_hash,
+        "difficulty": state.difficulty,
+        "pending_transactions": state.pending_transactions.len(),
+        "last_updated": state.last_updated
     })))
 }
@@ -1,4 +1,7 @@
 pub mod auth;
 pub mod wallet;
+pub mod beneficiaries;
 pub mod transactions;
 pub mod blockchain;
+pub mod p2p;
+pub mod statement;
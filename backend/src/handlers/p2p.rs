@@ -0,0 +1,122 @@
+use actix_web::{web, HttpResponse};
+use mongodb::Database;
+use serde_json::json;
+use crate::chain::BlockOutcome;
+use crate::db::Database as DbOps;
+use crate::error::ApiError;
+use crate::mempool::Mempool;
+use crate::p2p::{
+    self, BlockMessage, GetDataRequest, GetHeadersRequest, PeerManager, TransactionMessage, VersionMessage,
+    MAX_BLOCKS_PER_MESSAGE, MAX_HEADERS_PER_MESSAGE,
+};
+
+pub async fn version(
+    db: web::Data<Database>,
+    peers: web::Data<PeerManager>,
+    req: web::Json<VersionMessage>,
+) -> Result<HttpResponse, ApiError> {
+    peers.register_peer(&req)?;
+    Ok(HttpResponse::Ok().json(peers.local_version(&db).await?))
+}
+
+pub async fn get_peers(
+    peers: web::Data<PeerManager>,
+) -> Result<HttpResponse, ApiError> {
+    Ok(HttpResponse::Ok().json(peers.peers()))
+}
+
+pub async fn receive_block(
+    db: web::Data<Database>,
+    mempool: web::Data<Mempool>,
+    peers: web::Data<PeerManager>,
+    req: web::Json<BlockMessage>,
+) -> Result<HttpResponse, ApiError> {
+    let message = req.into_inner();
+
+    // A block on an unknown parent means we are behind the sender; catch up in the background.
+    // Only peers we handshook with are dialed, and never while another download is running.
+    if DbOps::get_block_by_hash(&db, &message.block.previous_hash).await?.is_none() {
+        if !peers.is_peer(&message.sender) {
+            return Err(ApiError::BadRequest(format!(
+                "Unknown parent block {} from unregistered peer",
+                message.block.previous_hash
+            )));
+        }
+        if peers.is_syncing() {
+            return Ok(HttpResponse::Accepted().json(json!({ "status": "syncing" })));
+        }
+        let sender = message.sender.clone();
+        actix_web::rt::spawn(async move {
+            if let Err(e) = peers.sync_from_peer(&db, &sender).await {
+                log::warn!("{}", e);
+            }
+        });
+        return Ok(HttpResponse::Accepted().json(json!({ "status": "syncing" })));
+    }
+
+    let block = message.block.clone();
+    let outcome = p2p::import_block(&db, &mempool, message).await?;
+    if matches!(outcome, BlockOutcome::Extended | BlockOutcome::Reorganized { .. }) {
+        actix_web::rt::spawn(async move {
+            peers.broadcast_block(&db, &block).await;
+        });
+    }
+    Ok(HttpResponse::Ok().json(outcome))
+}
+
+pub async fn receive_transaction(
+    db: web::Data<Database>,
+    mempool: web::Data<Mempool>,
+    peers: web::Data<PeerManager>,
+    req: web::Json<TransactionMessage>,
+) -> Result<HttpResponse, ApiError> {
+    let message = req.into_inner();
+    let tx = message.transaction.clone();
+
+    let accepted = mempool.add_transaction(&db, message.transaction, message.outputs).await?;
+    if accepted {
+        actix_web::rt::spawn(async move {
+            peers.broadcast_transaction(&db, &tx).await;
+        });
+    }
+    Ok(HttpResponse::Ok().json(json!({ "accepted": accepted })))
+}
+
+// Returns main-chain headers after the first locator hash we share with the requester
+pub async fn get_headers(
+    db: web::Data<Database>,
+    req: web::Json<GetHeadersRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let mut start = 0;
+    for hash in &req.locator {
+        if let Some(block) = DbOps::get_block_by_hash(&db, hash).await? {
+            if block.main_chain {
+                start = block.index + 1;
+                break;
+            }
+        }
+    }
+
+    let headers = DbOps::get_headers_from_index(&db, start, MAX_HEADERS_PER_MESSAGE).await?;
+    Ok(HttpResponse::Ok().json(headers))
+}
+
+// Returns full blocks, with the outputs they create, for the requested hashes
+pub async fn get_data(
+    db: web::Data<Database>,
+    peers: web::Data<PeerManager>,
+    req: web::Json<GetDataRequest>,
+) -> Result<HttpResponse, ApiError> {
+    if req.hashes.len() > MAX_BLOCKS_PER_MESSAGE {
+        return Err(ApiError::BadRequest(format!(
+            "At most {} blocks per request",
+            MAX_BLOCKS_PER_MESSAGE
+        )));
+    }
+
+    let mut messages = Vec::new();
+    for block in DbOps::get_blocks_by_hashes(&db, &req.hashes).await? {
+        messages.push(p2p::block_message(&db, &peers.node_url, block).await?);
+    }
+    Ok(HttpResponse::Ok().json(messages))
+}
@@ -0,0 +1,241 @@
+use actix_web::{web, HttpResponse};
+use chrono::{DateTime, Utc};
+use mongodb::Database;
+use crate::models::{EntryType, Statement, StatementEntry, StatementFormat, StatementQuery, Transaction, UTXO};
+use crate::db::Database as DbOps;
+use crate::error::ApiError;
+use crate::address;
+use crate::blockchain::{Blockchain, COINBASE_WALLET_ID};
+use crate::handlers::transactions::history_amount;
+use crate::handlers::wallet::paid_to_others;
+
+const ZAKAT_POOL_WALLET_ID: &str = "ZAKAT_POOL";
+const CURRENCY: &str = "CWC";
+
+pub async fn get_statement(
+    db: web::Data<Database>,
+    wallet_id: web::Path<String>,
+    query: web::Query<StatementQuery>,
+) -> Result<HttpResponse, ApiError> {
+    let wallet_id = address::parse(&wallet_id)?;
+
+    let to = query.to.unwrap_or_else(Utc::now);
+    if query.from > to {
+        return Err(ApiError::BadRequest("from must not be after to".to_string()));
+    }
+
+    let transactions = DbOps::get_confirmed_transactions_for_wallet(&db, &wallet_id, &to).await?;
+    let allocations = DbOps::get_wallet_allocations(&db, &wallet_id, &to).await?;
+    let statement = build_statement(&wallet_id, query.from, to, &transactions, &allocations);
+
+    let filename = format!(
+        "statement-{}-{}-{}",
+        wallet_id,
+        statement.from.format("%Y%m%d"),
+        statement.to.format("%Y%m%d")
+    );
+    Ok(match query.format {
+        StatementFormat::Json => HttpResponse::Ok().json(statement),
+        StatementFormat::Csv => HttpResponse::Ok()
+            .content_type("text/csv")
+            .insert_header(("Content-Disposition", format!("attachment; filename=\"{}.csv\"", filename)))
+            .body(render_csv(&statement)),
+        StatementFormat::Ofx => HttpResponse::Ok()
+            .content_type("application/x-ofx")
+            .insert_header(("Content-Disposition", format!("attachment; filename=\"{}.ofx\"", filename)))
+            .body(render_ofx(&statement)),
+    })
+}
+
+// Books every confirmed movement of funds up to `to` in date order. Movements before `from` make
+// up the opening balance, the rest are listed with the balance after each.
+fn build_statement(
+    wallet_id: &str,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+    transactions: &[Transaction],
+    allocations: &[UTXO],
+) -> Statement {
+    // Balances are filled in once the movements are in order
+    let movement = |date, hash: &str, entry_type, category: &str, counterparty: &str, description: String, amount| StatementEntry {
+        date,
+        transaction_hash: hash.to_string(),
+        entry_type,
+        category: category.to_string(),
+        counterparty: counterparty.to_string(),
+        description,
+        amount,
+        balance: 0.0,
+    };
+
+    let mut movements = Vec::new();
+    for utxo in allocations {
+        movements.push(movement(
+            utxo.created_at,
+            &utxo.transaction_hash,
+            EntryType::Credit,
+            "allocation",
+            "",
+            "Initial allocation".to_string(),
+            utxo.amount,
+        ));
+    }
+    for tx in transactions {
+        let description = tx.note.clone().unwrap_or_default();
+        if tx.sender_wallet_id == wallet_id {
+            // What left the wallet: payments to others plus the fee; change is not a movement
+            let amount = paid_to_others(tx) + tx.fee;
+            if amount <= 0.0 {
+                continue;
+            }
+            let category = if tx.recipient_wallet_id == ZAKAT_POOL_WALLET_ID { "zakat" } else { "transfer" };
+            let counterparty = if tx.recipient_wallet_id == wallet_id { "" } else { tx.recipient_wallet_id.as_str() };
+            movements.push(movement(tx.timestamp, &tx.transaction_hash, EntryType::Debit, category, counterparty, description, amount));
+        } else {
+            let category = if tx.sender_wallet_id == COINBASE_WALLET_ID { "mining_reward" } else { "transfer" };
+            movements.push(movement(
+                tx.timestamp,
+                &tx.transaction_hash,
+                EntryType::Credit,
+                category,
+                &tx.sender_wallet_id,
+                description,
+                history_amount(tx, wallet_id),
+            ));
+        }
+    }
+    movements.sort_by(|a, b| a.date.cmp(&b.date).then_with(|| a.transaction_hash.cmp(&b.transaction_hash)));
+
+    let signed = |entry: &StatementEntry| match entry.entry_type {
+        EntryType::Credit => entry.amount,
+        EntryType::Debit => -entry.amount,
+    };
+    let opening_balance: f64 = movements.iter().filter(|m| m.date < from).map(signed).sum();
+
+    let mut balance = opening_balance;
+    let mut total_credits = 0.0;
+    let mut total_debits = 0.0;
+    let mut entries: Vec<StatementEntry> = movements.into_iter().filter(|m| m.date >= from).collect();
+    for entry in &mut entries {
+        match entry.entry_type {
+            EntryType::Credit => total_credits += entry.amount,
+            EntryType::Debit => total_debits += entry.amount,
+        }
+        balance += signed(entry);
+        entry.balance = balance;
+    }
+
+    Statement {
+        wallet_id: wallet_id.to_string(),
+        from,
+        to,
+        generated_at: Utc::now(),
+        opening_balance,
+        total_credits,
+        total_debits,
+        closing_balance: balance,
+        entries,
+    }
+}
+
+fn render_csv(statement: &Statement) -> String {
+    let mut csv = String::from("date,transaction_hash,type,category,counterparty,description,debit,credit,balance\n");
+    csv.push_str(&format!(
+        "{},,,opening_balance,,Opening balance,,,{}\n",
+        statement.from.to_rfc3339(),
+        statement.opening_balance
+    ));
+    for entry in &statement.entries {
+        let (debit, credit) = match entry.entry_type {
+            EntryType::Debit => (entry.amount.to_string(), String::new()),
+            EntryType::Credit => (String::new(), entry.amount.to_string()),
+        };
+        csv.push_str(&format!(
+            "{},{},{},{},{},{},{},{},{}\n",
+            entry.date.to_rfc3339(),
+            entry.transaction_hash,
+            if entry.entry_type == EntryType::Credit { "credit" } else { "debit" },
+            entry.category,
+            csv_field(&entry.counterparty),
+            csv_field(&entry.description),
+            debit,
+            credit,
+            entry.balance
+        ));
+    }
+    csv.push_str(&format!(
+        "{},,,closing_balance,,Closing balance,{},{},{}\n",
+        statement.to.to_rfc3339(),
+        statement.total_debits,
+        statement.total_credits,
+        statement.closing_balance
+    ));
+    csv
+}
+
+// Quotes a field containing a separator, quote or line break
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+// OFX 2.2 bank statement. The opening balance has no dedicated element and goes in BALLIST.
+fn render_ofx(statement: &Statement) -> String {
+    let date = |d: &DateTime<Utc>| d.format("%Y%m%d%H%M%S").to_string();
+    let now = date(&statement.generated_at);
+
+    let mut transactions = String::new();
+    for entry in &statement.entries {
+        let (kind, amount) = match entry.entry_type {
+            EntryType::Credit => ("CREDIT", entry.amount),
+            EntryType::Debit => ("DEBIT", -entry.amount),
+        };
+        let name = if entry.counterparty.is_empty() { entry.category.as_str() } else { entry.counterparty.as_str() };
+        transactions.push_str(&format!(
+            "<STMTTRN><TRNTYPE>{}</TRNTYPE><DTPOSTED>{}</DTPOSTED><TRNAMT>{}</TRNAMT><FITID>{}</FITID><NAME>{}</NAME><MEMO>{}</MEMO></STMTTRN>\n",
+            kind,
+            date(&entry.date),
+            amount,
+            xml_escape(&entry.transaction_hash),
+            xml_escape(&name.chars().take(32).collect::<String>()),
+            xml_escape(&entry.description),
+        ));
+    }
+
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8" standalone="no"?>
+<?OFX OFXHEADER="200" VERSION="220" SECURITY="NONE" OLDFILEUID="NONE" NEWFILEUID="NONE"?>
+<OFX>
+<SIGNONMSGSRSV1><SONRS><STATUS><CODE>0</CODE><SEVERITY>INFO</SEVERITY></STATUS><DTSERVER>{now}</DTSERVER><LANGUAGE>ENG</LANGUAGE></SONRS></SIGNONMSGSRSV1>
+<BANKMSGSRSV1><STMTTRNRS><TRNUID>{now}</TRNUID><STATUS><CODE>0</CODE><SEVERITY>INFO</SEVERITY></STATUS>
+<STMTRS><CURDEF>{currency}</CURDEF>
+<BANKACCTFROM><BANKID>{bank}</BANKID><ACCTID>{account}</ACCTID><ACCTTYPE>CHECKING</ACCTTYPE></BANKACCTFROM>
+<BANKTRANLIST><DTSTART>{start}</DTSTART><DTEND>{end}</DTEND>
+{transactions}</BANKTRANLIST>
+<LEDGERBAL><BALAMT>{closing}</BALAMT><DTASOF>{end}</DTASOF></LEDGERBAL>
+<BALLIST><BAL><NAME>Opening balance</NAME><DESC>Balance at statement start</DESC><BALTYPE>DOLLAR</BALTYPE><VALUE>{opening}</VALUE><DTASOF>{start}</DTASOF></BAL></BALLIST>
+</STMTRS></STMTTRNRS></BANKMSGSRSV1>
+</OFX>
+"#,
+        now = now,
+        currency = CURRENCY,
+        bank = xml_escape(&Blockchain::chain_id()),
+        account = xml_escape(&statement.wallet_id),
+        start = date(&statement.from),
+        end = date(&statement.to),
+        transactions = transactions,
+        closing = statement.closing_balance,
+        opening = statement.opening_balance,
+    )
+}
+
+fn xml_escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
@@ -1,135 +1,764 @@
-use actix_web::{web, HttpResponse};
+use actix_web::{web, HttpMessage, HttpRequest, HttpResponse};
 use mongodb::Database;
 use serde_json::json;
 use uuid::Uuid;
 use chrono::Utc;
-use crate::models::{SendMoneyRequest, Transaction, UTXO, TransactionResponse};
+use crate::models::{
+    BatchPaymentQuery, BatchPaymentRequest, CancelTransactionRequest, HistoryQuery, IdempotencyRecord, Payment,
+    PrepareTransactionRequest, ReplaceTransactionRequest, SendMoneyRequest, SubmitTransactionRequest, Transaction, TransactionResponse, TransactionStatus, TxOutput, User, UTXO,
+};
 use crate::db::Database as DbOps;
 use crate::error::ApiError;
-use crate::crypto::{hash_sha256, sign_transaction, verify_signature, calculate_zakat};
+use crate::mempool::{Mempool, FEE_ESTIMATE_BLOCKS};
+use crate::p2p::PeerManager;
+use crate::handlers::beneficiaries::check_large_transfer_policy;
+use crate::address;
+use crate::coin_selection::{dust_threshold, min_confirmations, CoinSelection, Selection};
+use crate::blockchain::{Blockchain, MAX_BLOCK_SIZE, MAX_TRANSACTION_OUTPUTS};
+use crate::crypto::{
+    calculate_zakat, generate_wallet_id, hash_sha256, is_valid_public_key, sign_transaction, verify_signature,
+};
+
+// Keeps a consolidation well inside the block size limit
+pub const MAX_CONSOLIDATION_INPUTS: usize = 200;
+pub const DEFAULT_IDEMPOTENCY_KEY_TTL_HOURS: i64 = 24;
+pub const DEFAULT_HISTORY_PAGE_SIZE: usize = 50;
+pub const MAX_HISTORY_PAGE_SIZE: usize = 200;
+const MAX_IDEMPOTENCY_KEY_LENGTH: usize = 255;
 
 pub async fn send_money(
     db: web::Data<Database>,
+    mempool: web::Data<Mempool>,
+    peers: web::Data<PeerManager>,
+    http_req: HttpRequest,
     req: web::Json<SendMoneyRequest>,
+) -> Result<HttpResponse, ApiError> {
+    send(db, mempool, peers, &http_req, req.into_inner()).await
+}
+
+// Sends a single payment; shared by the send and send-to-beneficiary endpoints
+pub async fn send(
+    db: web::Data<Database>,
+    mempool: web::Data<Mempool>,
+    peers: web::Data<PeerManager>,
+    http_req: &HttpRequest,
+    req: SendMoneyRequest,
 ) -> Result<HttpResponse, ApiError> {
     // Validate sender wallet exists
     let sender = DbOps::find_user_by_wallet_id(&db, &req.sender_wallet_id)
         .await?
         .ok_or_else(|| ApiError::InvalidWalletId)?;
 
-    // Validate recipient wallet exists
-    let _recipient = DbOps::find_user_by_wallet_id(&db, &req.recipient_wallet_id)
+    // A retry carrying the same Idempotency-Key gets the original outcome instead of paying again
+    let idempotency_id = match idempotency_key(http_req)? {
+        Some(key) => {
+            let body = serde_json::to_vec(&req).map_err(|e| ApiError::InternalError(e.to_string()))?;
+            let now = Utc::now();
+            let record = IdempotencyRecord {
+                id: format!("{}:{}", sender.wallet_id, key),
+                request_hash: hash_sha256(&body),
+                transaction_hash: None,
+                created_at: now,
+                expires_at: mongodb::bson::DateTime::from_millis(
+                    (now + chrono::Duration::hours(idempotency_key_ttl_hours())).timestamp_millis(),
+                ),
+            };
+            if let Some(existing) = DbOps::reserve_idempotency_key(&db, &record).await? {
+                return replay_send(&db, existing, &record.request_hash).await;
+            }
+            Some(record.id)
+        }
+        None => None,
+    };
+
+    let payments = vec![Payment {
+        recipient_wallet_id: req.recipient_wallet_id.clone(),
+        amount: req.amount,
+    }];
+    let result = create_payment(
+        &db,
+        &mempool,
+        &sender,
+        &payments,
+        req.fee,
+        req.note.clone(),
+        req.coin_selection,
+    )
+    .await;
+    let transaction = match (result, &idempotency_id) {
+        // The payment is already in the mempool, so failing to record it under the key must not
+        // fail the request; a retry still finds the transaction through whichever write succeeded
+        (Ok(transaction), Some(id)) => {
+            if let Err(e) = DbOps::set_transaction_idempotency_key(&db, &transaction.transaction_hash, id).await {
+                log::warn!(
+                    "Could not tag transaction {} with idempotency key {}: {}",
+                    transaction.transaction_hash,
+                    id,
+                    e
+                );
+            }
+            if let Err(e) = DbOps::complete_idempotency_key(&db, id, &transaction.transaction_hash).await {
+                log::error!(
+                    "Could not complete idempotency key {} with transaction {}: {}",
+                    id,
+                    transaction.transaction_hash,
+                    e
+                );
+            }
+            transaction
+        }
+        (Ok(transaction), None) => transaction,
+        (Err(e), Some(id)) => {
+            DbOps::release_idempotency_key(&db, id).await?;
+            return Err(e);
+        }
+        (Err(e), None) => return Err(e),
+    };
+
+    // Relay to the other nodes
+    relay_transaction(db.clone(), peers, transaction.clone());
+
+    // Process zakat deduction
+    process_zakat_deduction(&db, &req.sender_wallet_id).await?;
+
+    Ok(HttpResponse::Created().json(TransactionResponse {
+        transaction_hash: transaction.transaction_hash,
+        status: TransactionStatus::InMempool,
+    }))
+}
+
+fn idempotency_key(http_req: &HttpRequest) -> Result<Option<String>, ApiError> {
+    let Some(value) = http_req.headers().get("Idempotency-Key") else {
+        return Ok(None);
+    };
+    let key = value
+        .to_str()
+        .map(str::trim)
+        .map_err(|_| ApiError::BadRequest("Idempotency-Key must be printable ASCII".to_string()))?;
+    if key.is_empty() || key.len() > MAX_IDEMPOTENCY_KEY_LENGTH {
+        return Err(ApiError::BadRequest(format!(
+            "Idempotency-Key must be 1 to {} characters",
+            MAX_IDEMPOTENCY_KEY_LENGTH
+        )));
+    }
+    Ok(Some(key.to_string()))
+}
+
+fn idempotency_key_ttl_hours() -> i64 {
+    std::env::var("IDEMPOTENCY_KEY_TTL_HOURS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_IDEMPOTENCY_KEY_TTL_HOURS)
+}
+
+// Answers a retried send from the stored outcome of the first request
+async fn replay_send(db: &Database, record: IdempotencyRecord, request_hash: &str) -> Result<HttpResponse, ApiError> {
+    if record.request_hash != request_hash {
+        return Err(ApiError::BadRequest(
+            "Idempotency-Key was already used for a different request".to_string(),
+        ));
+    }
+    // The record may have missed its transaction hash; the transaction then carries the key itself
+    let transaction = match &record.transaction_hash {
+        Some(tx_hash) => DbOps::get_transaction_by_hash(db, tx_hash).await?,
+        None => DbOps::find_transaction_by_idempotency_key(db, &record.id).await?,
+    };
+    let (tx_hash, status) = match (record.transaction_hash, transaction) {
+        (_, Some(tx)) => (tx.transaction_hash, tx.status),
+        (Some(tx_hash), None) => (tx_hash, TransactionStatus::InMempool),
+        (None, None) => {
+            return Err(ApiError::Conflict(
+                "A request with this Idempotency-Key is still being processed".to_string(),
+            ))
+        }
+    };
+
+    Ok(HttpResponse::Ok()
+        .insert_header(("Idempotent-Replayed", "true"))
+        .json(TransactionResponse {
+            transaction_hash: tx_hash,
+            status,
+        }))
+}
+
+// Pays many wallets in one signed transaction. Accepts a JSON BatchPaymentRequest, or a CSV body
+// (Content-Type: text/csv) with a header row naming recipient_wallet_id and amount columns and
+// the sender, fee and note given as query parameters.
+pub async fn send_batch(
+    db: web::Data<Database>,
+    mempool: web::Data<Mempool>,
+    peers: web::Data<PeerManager>,
+    http_req: HttpRequest,
+    body: web::Bytes,
+) -> Result<HttpResponse, ApiError> {
+    let batch = if http_req.content_type() == "text/csv" {
+        let query = web::Query::<BatchPaymentQuery>::from_query(http_req.query_string())
+            .map_err(|e| ApiError::BadRequest(e.to_string()))?
+            .into_inner();
+        let text = std::str::from_utf8(&body).map_err(|_| ApiError::BadRequest("CSV must be UTF-8".to_string()))?;
+        BatchPaymentRequest {
+            sender_wallet_id: query.sender_wallet_id,
+            payments: parse_payment_csv(text)?,
+            fee: query.fee,
+            note: query.note,
+            coin_selection: query.coin_selection,
+        }
+    } else {
+        serde_json::from_slice::<BatchPaymentRequest>(&body).map_err(|e| ApiError::BadRequest(e.to_string()))?
+    };
+
+    let sender = DbOps::find_user_by_wallet_id(&db, &batch.sender_wallet_id)
         .await?
-        .ok_or_else(|| ApiError::InvalidWalletId)?;
+        .ok_or(ApiError::InvalidWalletId)?;
+    let transaction = create_payment(
+        &db,
+        &mempool,
+        &sender,
+        &batch.payments,
+        batch.fee,
+        batch.note.clone(),
+        batch.coin_selection,
+    )
+    .await?;
+    relay_transaction(db.clone(), peers, transaction.clone());
+
+    Ok(HttpResponse::Created().json(json!({
+        "transaction_hash": transaction.transaction_hash,
+        "status": TransactionStatus::InMempool,
+        "payments": batch.payments.len(),
+        "total": transaction.amount,
+        "fee": transaction.fee
+    })))
+}
 
-    // Get sender's unspent UTXOs
-    let mut utxos = DbOps::get_utxos_for_wallet(&db, &req.sender_wallet_id).await?;
-    let total_balance: f64 = utxos.iter().map(|u| u.amount).sum();
+fn parse_payment_csv(text: &str) -> Result<Vec<Payment>, ApiError> {
+    let mut lines = text.lines().enumerate().filter(|(_, line)| !line.trim().is_empty());
+    let (_, header) = lines
+        .next()
+        .ok_or_else(|| ApiError::BadRequest("CSV batch is empty".to_string()))?;
+    let columns: Vec<String> = header.split(',').map(|c| c.trim().to_lowercase()).collect();
+    let column = |name: &str| {
+        columns
+            .iter()
+            .position(|c| c == name)
+            .ok_or_else(|| ApiError::BadRequest(format!("CSV header has no {} column", name)))
+    };
+    let recipient_column = column("recipient_wallet_id")?;
+    let amount_column = column("amount")?;
 
-    // Check sufficient balance
-    if total_balance < req.amount {
-        return Err(ApiError::InsufficientBalance);
+    let mut payments = Vec::new();
+    for (number, line) in lines {
+        let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+        let invalid = || ApiError::BadRequest(format!("Invalid payment on CSV line {}", number + 1));
+        let recipient = fields.get(recipient_column).filter(|r| !r.is_empty()).ok_or_else(invalid)?;
+        let amount = fields
+            .get(amount_column)
+            .and_then(|a| a.parse::<f64>().ok())
+            .ok_or_else(invalid)?;
+        payments.push(Payment {
+            recipient_wallet_id: address::parse(recipient)?,
+            amount,
+        });
     }
+    Ok(payments)
+}
 
-    // Select UTXOs for transaction
-    let mut selected_utxos = Vec::new();
-    let mut accumulated = 0.0;
-    
-    for utxo in utxos.iter_mut() {
-        selected_utxos.push(utxo.clone());
-        accumulated += utxo.amount;
-        if accumulated >= req.amount {
-            break;
+// Selects inputs with the chosen strategy, signs and submits one transaction paying every
+// payment, plus change. Without an explicit fee, pays the current "normal" rate for the estimated size.
+async fn create_payment(
+    db: &Database,
+    mempool: &Mempool,
+    sender: &User,
+    payments: &[Payment],
+    fee: Option<f64>,
+    note: Option<String>,
+    coin_selection: CoinSelection,
+) -> Result<Transaction, ApiError> {
+    check_large_transfer_policy(sender, payments)?;
+    let selection = select_payment_inputs(db, mempool, &sender.wallet_id, payments, fee, note.as_deref(), coin_selection).await?;
+
+    let nonce = next_nonce(db, &sender.wallet_id).await?;
+    let (transaction, outputs) = build_transaction(sender, payments, selection.fee, note, &selection.inputs, nonce);
+
+    // The mempool validates the transaction, stores it with its outputs and marks the inputs spent
+    mempool.add_transaction(db, transaction.clone(), outputs).await?;
+    Ok(transaction)
+}
+
+// Validates the payments and picks the sender's inputs and fee for them. Recipients only need a
+// well-formed address; they do not have to be registered users.
+async fn select_payment_inputs(
+    db: &Database,
+    mempool: &Mempool,
+    sender_wallet_id: &str,
+    payments: &[Payment],
+    fee: Option<f64>,
+    note: Option<&str>,
+    coin_selection: CoinSelection,
+) -> Result<Selection, ApiError> {
+    if payments.is_empty() || payments.len() >= MAX_TRANSACTION_OUTPUTS {
+        return Err(ApiError::BadRequest(format!(
+            "A transaction pays between 1 and {} recipients",
+            MAX_TRANSACTION_OUTPUTS - 1
+        )));
+    }
+    if payments.iter().any(|p| p.amount <= 0.0) {
+        return Err(ApiError::BadRequest("Payment amounts must be positive".to_string()));
+    }
+    if matches!(fee, Some(fee) if fee < 0.0) {
+        return Err(ApiError::BadRequest("Fee cannot be negative".to_string()));
+    }
+
+    let fee_rate = match fee {
+        Some(_) => 0.0,
+        None => {
+            let recent = DbOps::get_recent_blocks(db, FEE_ESTIMATE_BLOCKS).await?;
+            mempool.estimate_fees(&recent, MAX_BLOCK_SIZE).fee_rates.normal
         }
+    };
+    // One output per payment, plus change if there is any
+    let fee_for = |inputs: usize, with_change: bool| {
+        fee.unwrap_or_else(|| {
+            let outputs = payments.len() + usize::from(with_change);
+            fee_rate * Mempool::estimated_transaction_size(inputs, outputs, note) as f64
+        })
+    };
+    let total: f64 = payments.iter().map(|p| p.amount).sum();
+
+    // Get sender's unspent UTXOs with enough confirmations
+    let utxos = DbOps::get_spendable_utxos(db, sender_wallet_id, min_confirmations()).await?;
+
+    // Select UTXOs for transaction; change below the dust threshold goes to the fee
+    coin_selection
+        .selector()
+        .select(&utxos, total, &fee_for, dust_threshold())
+        .ok_or(ApiError::InsufficientBalance)
+}
+
+// Builds an unsigned payment from a wallet whose key this node does not hold. The client signs
+// `signing_payload` with its Ed25519 key and submits the transaction with the signature added.
+pub async fn prepare_transaction(
+    db: web::Data<Database>,
+    mempool: web::Data<Mempool>,
+    req: web::Json<PrepareTransactionRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let req = req.into_inner();
+    if !is_valid_public_key(&req.sender_public_key) {
+        return Err(ApiError::BadRequest("sender_public_key must be a hex Ed25519 public key".to_string()));
     }
+    let sender_wallet_id = generate_wallet_id(&req.sender_public_key);
+    check_registered_sender_policy(&db, &sender_wallet_id, &req.payments).await?;
+    let selection = select_payment_inputs(
+        &db,
+        &mempool,
+        &sender_wallet_id,
+        &req.payments,
+        req.fee,
+        req.note.as_deref(),
+        req.coin_selection,
+    )
+    .await?;
 
-    // Create transaction
-    let input_utxo_ids: Vec<String> = selected_utxos.iter().map(|u| u.utxo_id.clone()).collect();
-    let output_utxo_id = Uuid::new_v4().to_string();
-    let change_amount = accumulated - req.amount;
+    let nonce = next_nonce(&db, &sender_wallet_id).await?;
+    let transaction = unsigned_transaction(
+        &sender_wallet_id,
+        &req.sender_public_key,
+        &req.payments,
+        selection.fee,
+        req.note,
+        &selection.inputs,
+        nonce,
+    );
+    Ok(HttpResponse::Ok().json(json!({
+        "signing_payload": Blockchain::signing_payload(&transaction),
+        "transaction": transaction,
+    })))
+}
+
+// Externally signed payments from a registered user's wallet follow the same large transfer
+// policy as payments the node signs for them
+async fn check_registered_sender_policy(db: &Database, wallet_id: &str, payments: &[Payment]) -> Result<(), ApiError> {
+    match DbOps::find_user_by_wallet_id(db, wallet_id).await? {
+        Some(user) => check_large_transfer_policy(&user, payments),
+        None => Ok(()),
+    }
+}
+
+// Accepts a transaction signed outside this node, e.g. one from `prepare`
+pub async fn submit_transaction(
+    db: web::Data<Database>,
+    mempool: web::Data<Mempool>,
+    peers: web::Data<PeerManager>,
+    req: web::Json<SubmitTransactionRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let mut transaction = req.into_inner().transaction;
+    transaction.id = None;
+    transaction.status = TransactionStatus::Pending;
+    transaction.block_hash = None;
+
+    // Only the signed multi-output form says exactly which outputs to create
+    if transaction.outputs.is_empty() {
+        return Err(ApiError::BadRequest("Transaction must list its outputs".to_string()));
+    }
+    for output in &transaction.outputs {
+        if address::parse(&output.wallet_id)? != output.wallet_id {
+            return Err(ApiError::BadRequest("Output wallet IDs must be hex".to_string()));
+        }
+    }
+    if transaction.transaction_hash != hash_sha256(Blockchain::signing_payload(&transaction).as_bytes()) {
+        return Err(ApiError::BadRequest("Transaction hash does not match its contents".to_string()));
+    }
+    let payments: Vec<Payment> = transaction
+        .outputs
+        .iter()
+        .map(|o| Payment {
+            recipient_wallet_id: o.wallet_id.clone(),
+            amount: o.amount,
+        })
+        .collect();
+    check_registered_sender_policy(&db, &transaction.sender_wallet_id, &payments).await?;
+    let output_ids: Vec<String> = transaction.outputs.iter().map(|o| o.utxo_id.clone()).collect();
+    if !DbOps::get_utxos_by_ids(&db, &output_ids).await?.is_empty() {
+        return Err(ApiError::BadRequest("Output IDs are already in use".to_string()));
+    }
+
+    // Signature, key ownership of the inputs, amounts, fee and nonce are checked by the mempool
+    let outputs = output_utxos(&transaction);
+    if !mempool.add_transaction(&db, transaction.clone(), outputs).await? {
+        return Err(ApiError::Conflict("Transaction is already known".to_string()));
+    }
+    relay_transaction(db.clone(), peers, transaction.clone());
+
+    Ok(HttpResponse::Created().json(TransactionResponse {
+        transaction_hash: transaction.transaction_hash,
+        status: TransactionStatus::InMempool,
+    }))
+}
+
+// Merges a wallet's smallest unspent outputs into a single output back to the same wallet,
+// paying `fee_rate` for the transaction's size
+pub async fn consolidate_utxos(
+    db: &Database,
+    mempool: &Mempool,
+    wallet_id: &str,
+    max_utxo_amount: Option<f64>,
+    max_inputs: Option<usize>,
+    fee_rate: f64,
+) -> Result<Transaction, ApiError> {
+    let user = DbOps::find_user_by_wallet_id(db, wallet_id)
+        .await?
+        .ok_or(ApiError::InvalidWalletId)?;
+
+    let mut inputs: Vec<UTXO> = DbOps::get_spendable_utxos(db, wallet_id, min_confirmations())
+        .await?
+        .into_iter()
+        .filter(|u| max_utxo_amount.is_none_or(|max| u.amount <= max))
+        .collect();
+    inputs.sort_by(|a, b| a.amount.total_cmp(&b.amount));
+    inputs.truncate(max_inputs.unwrap_or(MAX_CONSOLIDATION_INPUTS).min(MAX_CONSOLIDATION_INPUTS));
+    if inputs.len() < 2 {
+        return Err(ApiError::BadRequest("Fewer than two outputs to consolidate".to_string()));
+    }
+
+    let note = "UTXO consolidation".to_string();
+    let total: f64 = inputs.iter().map(|u| u.amount).sum();
+    let amount = total - fee_rate * Mempool::estimated_transaction_size(inputs.len(), 1, Some(&note)) as f64;
+    if amount <= 0.0 {
+        return Err(ApiError::BadRequest("Outputs are worth less than the fee to merge them".to_string()));
+    }
+    // Whatever the rounding, the inputs are spent in full
+    let fee = total - amount;
+
+    let payment = [Payment {
+        recipient_wallet_id: wallet_id.to_string(),
+        amount,
+    }];
+    let nonce = next_nonce(db, wallet_id).await?;
+    let (transaction, outputs) = build_transaction(&user, &payment, fee, Some(note), &inputs, nonce);
+    mempool.add_transaction(db, transaction.clone(), outputs).await?;
+    Ok(transaction)
+}
+
+// Signs a transaction spending `inputs` into one output per payment, with any remainder after
+// the payments and fee returned to the sender as change
+fn build_transaction(
+    sender: &User,
+    payments: &[Payment],
+    fee: f64,
+    note: Option<String>,
+    inputs: &[UTXO],
+    nonce: u64,
+) -> (Transaction, Vec<UTXO>) {
+    let mut transaction = unsigned_transaction(&sender.wallet_id, &sender.public_key, payments, fee, note, inputs, nonce);
+    transaction.digital_signature = sign_transaction(&Blockchain::signing_payload(&transaction), &sender.private_key_encrypted);
+    let utxos = output_utxos(&transaction);
+    (transaction, utxos)
+}
+
+// A payment with its hash set but no signature yet
+fn unsigned_transaction(
+    sender_wallet_id: &str,
+    sender_public_key: &str,
+    payments: &[Payment],
+    fee: f64,
+    note: Option<String>,
+    inputs: &[UTXO],
+    nonce: u64,
+) -> Transaction {
+    let mut outputs: Vec<TxOutput> = payments
+        .iter()
+        .map(|p| TxOutput {
+            utxo_id: Uuid::new_v4().to_string(),
+            wallet_id: p.recipient_wallet_id.clone(),
+            amount: p.amount,
+        })
+        .collect();
+
+    let amount: f64 = payments.iter().map(|p| p.amount).sum();
+    let change_amount = inputs.iter().map(|u| u.amount).sum::<f64>() - amount - fee;
     let change_utxo_id = if change_amount > 0.0 {
-        Some(Uuid::new_v4().to_string())
+        let change_id = Uuid::new_v4().to_string();
+        outputs.push(TxOutput {
+            utxo_id: change_id.clone(),
+            wallet_id: sender_wallet_id.to_string(),
+            amount: change_amount,
+        });
+        Some(change_id)
     } else {
         None
     };
 
-    // Create signature payload
-    let sig_payload = format!(
-        "{}{}{}{}",
-        req.sender_wallet_id, req.recipient_wallet_id, req.amount, Utc::now().timestamp()
-    );
-    let signature = sign_transaction(&sig_payload, &sender.private_key_encrypted);
-
-    let transaction = Transaction {
+    let mut transaction = Transaction {
         id: None,
-        transaction_hash: hash_sha256(sig_payload.as_bytes()),
-        sender_wallet_id: req.sender_wallet_id.clone(),
-        recipient_wallet_id: req.recipient_wallet_id.clone(),
-        amount: req.amount,
-        note: req.note.clone(),
+        transaction_hash: String::new(),
+        sender_wallet_id: sender_wallet_id.to_string(),
+        recipient_wallet_id: outputs[0].wallet_id.clone(),
+        amount,
+        fee,
+        note,
         timestamp: Utc::now(),
-        sender_public_key: sender.public_key.clone(),
-        digital_signature: signature,
-        input_utxos: input_utxo_ids.clone(),
-        output_utxo: output_utxo_id.clone(),
-        change_utxo: change_utxo_id.clone(),
-        status: "pending".to_string(),
+        sender_public_key: sender_public_key.to_string(),
+        digital_signature: String::new(),
+        input_utxos: inputs.iter().map(|u| u.utxo_id.clone()).collect(),
+        output_utxo: outputs[0].utxo_id.clone(),
+        change_utxo: change_utxo_id,
+        outputs,
+        chain_id: Some(Blockchain::chain_id()),
+        nonce: Some(nonce),
+        status: TransactionStatus::Pending,
         block_hash: None,
     };
 
-    // Save transaction
-    DbOps::create_transaction(&db, &transaction).await?;
-
-    // Create output UTXO
-    let output_utxo = UTXO {
-        id: None,
-        utxo_id: output_utxo_id,
-        wallet_id: req.recipient_wallet_id.clone(),
-        amount: req.amount,
-        status: "unspent".to_string(),
-        block_hash: "pending".to_string(),
-        transaction_hash: transaction.transaction_hash.clone(),
-        created_at: Utc::now(),
-    };
-    DbOps::create_utxo(&db, &output_utxo).await?;
+    // The payload covers every output; the timestamp is stored on the transaction so it can be verified later
+    transaction.transaction_hash = hash_sha256(Blockchain::signing_payload(&transaction).as_bytes());
+    transaction
+}
 
-    // Create change UTXO if needed
-    if let Some(change_id) = change_utxo_id {
-        let change_utxo = UTXO {
+// UTXOs created by a transaction's outputs, owned by whichever wallet each pays
+fn output_utxos(transaction: &Transaction) -> Vec<UTXO> {
+    transaction
+        .outputs
+        .iter()
+        .map(|o| UTXO {
             id: None,
-            utxo_id: change_id,
-            wallet_id: req.sender_wallet_id.clone(),
-            amount: change_amount,
+            utxo_id: o.utxo_id.clone(),
+            wallet_id: o.wallet_id.clone(),
+            amount: o.amount,
             status: "unspent".to_string(),
             block_hash: "pending".to_string(),
             transaction_hash: transaction.transaction_hash.clone(),
             created_at: Utc::now(),
-        };
-        DbOps::create_utxo(&db, &change_utxo).await?;
+        })
+        .collect()
+}
+
+// Nonce for a new transaction: one past the highest the wallet has signed, so it is never reused
+async fn next_nonce(db: &Database, wallet_id: &str) -> Result<u64, ApiError> {
+    Ok(DbOps::get_last_nonce(db, wallet_id, false).await?.map_or(0, |last| last + 1))
+}
+
+// Payments made by a transaction, excluding change back to the sender
+fn payments_of(tx: &Transaction) -> Vec<Payment> {
+    if tx.outputs.is_empty() {
+        return vec![Payment {
+            recipient_wallet_id: tx.recipient_wallet_id.clone(),
+            amount: tx.amount,
+        }];
     }
+    tx.outputs
+        .iter()
+        .filter(|o| tx.change_utxo.as_ref() != Some(&o.utxo_id))
+        .map(|o| Payment {
+            recipient_wallet_id: o.wallet_id.clone(),
+            amount: o.amount,
+        })
+        .collect()
+}
 
-    // Mark input UTXOs as spent
-    for utxo_id in input_utxo_ids {
-        DbOps::mark_utxo_spent(&db, &utxo_id).await?;
+// Loads a transaction that its sender may still replace: unconfirmed and waiting in the mempool
+async fn replaceable_transaction(
+    db: &Database,
+    mempool: &Mempool,
+    tx_hash: &str,
+    sender_wallet_id: &str,
+) -> Result<(Transaction, User, Vec<UTXO>), ApiError> {
+    let original = DbOps::get_transaction_by_hash(db, tx_hash)
+        .await?
+        .ok_or_else(|| ApiError::NotFound("Transaction not found".to_string()))?;
+    if original.sender_wallet_id != sender_wallet_id {
+        return Err(ApiError::Unauthorized("Only the sender can replace a transaction".to_string()));
+    }
+    if !original.status.is_unconfirmed() || !mempool.contains(tx_hash) {
+        return Err(ApiError::BadRequest(format!(
+            "Transaction {} is {} and can no longer be replaced",
+            tx_hash, original.status
+        )));
     }
 
-    // Process zakat deduction
-    process_zakat_deduction(&db, &req.sender_wallet_id).await?;
+    let sender = DbOps::find_user_by_wallet_id(db, sender_wallet_id)
+        .await?
+        .ok_or_else(|| ApiError::InvalidWalletId)?;
+    let inputs = DbOps::get_utxos_by_ids(db, &original.input_utxos).await?;
+    Ok((original, sender, inputs))
+}
 
-    Ok(HttpResponse::Created().json(TransactionResponse {
-        transaction_hash: transaction.transaction_hash,
-        status: "pending".to_string(),
-    }))
+pub fn relay_transaction(db: web::Data<Database>, peers: web::Data<PeerManager>, tx: Transaction) {
+    actix_web::rt::spawn(async move {
+        peers.broadcast_transaction(&db, &tx).await;
+    });
+}
+
+// Re-sends an unconfirmed payment from the same inputs with a higher fee (replace-by-fee)
+pub async fn replace_transaction(
+    db: web::Data<Database>,
+    mempool: web::Data<Mempool>,
+    peers: web::Data<PeerManager>,
+    tx_hash: web::Path<String>,
+    req: web::Json<ReplaceTransactionRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let tx_hash = tx_hash.into_inner();
+    let (original, sender, inputs) = replaceable_transaction(&db, &mempool, &tx_hash, &req.sender_wallet_id).await?;
+
+    // At least what replacement requires, and no less than the current "normal" rate
+    let payments = payments_of(&original);
+    let size = Mempool::estimated_transaction_size(inputs.len(), payments.len() + 1, original.note.as_deref());
+    let fee = match req.fee {
+        Some(fee) => fee,
+        None => {
+            let recent = DbOps::get_recent_blocks(&db, FEE_ESTIMATE_BLOCKS).await?;
+            let normal = mempool.estimate_fees(&recent, MAX_BLOCK_SIZE).fee_rates.normal * size as f64;
+            mempool.replacement_fee(&original, size).max(normal)
+        }
+    };
+    let available: f64 = inputs.iter().map(|u| u.amount).sum();
+    if available < original.amount + fee {
+        return Err(ApiError::InsufficientBalance);
+    }
+
+    // Reusing the nonce is what lets the replacement take the original's place
+    let nonce = match original.nonce {
+        Some(nonce) => nonce,
+        None => next_nonce(&db, &sender.wallet_id).await?,
+    };
+    let (replacement, outputs) = build_transaction(&sender, &payments, fee, original.note.clone(), &inputs, nonce);
+    mempool.add_transaction(&db, replacement.clone(), outputs).await?;
+    relay_transaction(db.clone(), peers, replacement.clone());
+
+    Ok(HttpResponse::Created().json(json!({
+        "transaction_hash": replacement.transaction_hash,
+        "replaces": tx_hash,
+        "fee": fee,
+        "status": TransactionStatus::InMempool
+    })))
+}
+
+// Cancels an unconfirmed payment by replacing it with one that pays its inputs back to the sender.
+// The replacement is relayed like any other, so peers drop the original as well.
+pub async fn cancel_transaction(
+    db: web::Data<Database>,
+    mempool: web::Data<Mempool>,
+    peers: web::Data<PeerManager>,
+    tx_hash: web::Path<String>,
+    req: web::Json<CancelTransactionRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let tx_hash = tx_hash.into_inner();
+    let (original, sender, inputs) = replaceable_transaction(&db, &mempool, &tx_hash, &req.sender_wallet_id).await?;
+
+    let note = format!("Cancels {}", tx_hash);
+    let size = Mempool::estimated_transaction_size(inputs.len(), 1, Some(&note));
+    let fee = mempool.replacement_fee(&original, size);
+    let refund = inputs.iter().map(|u| u.amount).sum::<f64>() - fee;
+    if refund <= 0.0 {
+        return Err(ApiError::BadRequest("Inputs do not cover the fee needed to cancel".to_string()));
+    }
+
+    let refund_payment = [Payment {
+        recipient_wallet_id: sender.wallet_id.clone(),
+        amount: refund,
+    }];
+    let nonce = match original.nonce {
+        Some(nonce) => nonce,
+        None => next_nonce(&db, &sender.wallet_id).await?,
+    };
+    let (cancellation, outputs) = build_transaction(&sender, &refund_payment, fee, Some(note), &inputs, nonce);
+    mempool.add_transaction(&db, cancellation.clone(), outputs).await?;
+    relay_transaction(db.clone(), peers, cancellation.clone());
+
+    Ok(HttpResponse::Created().json(json!({
+        "transaction_hash": cancellation.transaction_hash,
+        "replaces": tx_hash,
+        "refunded": refund,
+        "fee": fee,
+        "status": TransactionStatus::InMempool
+    })))
 }
 
 pub async fn get_history(
     db: web::Data<Database>,
     wallet_id: web::Path<String>,
+    query: web::Query<HistoryQuery>,
 ) -> Result<HttpResponse, ApiError> {
-    let wallet_id = wallet_id.into_inner();
-    let transactions = DbOps::get_transactions_for_wallet(&db, &wallet_id).await?;
+    let wallet_id = address::parse(&wallet_id)?;
+    let limit = query.limit.unwrap_or(DEFAULT_HISTORY_PAGE_SIZE).clamp(1, MAX_HISTORY_PAGE_SIZE);
+    if let (Some(from), Some(to)) = (query.from, query.to) {
+        if from > to {
+            return Err(ApiError::BadRequest("from must not be after to".to_string()));
+        }
+    }
 
-    let response: Vec<_> = transactions
+    let mut after = match &query.cursor {
+        Some(hash) => Some(
+            DbOps::get_transaction_by_hash(&db, hash)
+                .await?
+                .ok_or_else(|| ApiError::BadRequest("Invalid cursor".to_string()))?,
+        ),
+        None => None,
+    };
+
+    // Fetch one extra item to know whether another page follows. Amount filters are applied
+    // here, so keep reading until the page is full or the history runs out.
+    let mut page = Vec::new();
+    loop {
+        let batch = DbOps::get_wallet_history(&db, &wallet_id, &query, after.as_ref(), limit as i64 + 1).await?;
+        let exhausted = batch.len() <= limit;
+        after = batch.last().cloned();
+        page.extend(batch.into_iter().filter(|tx| {
+            let amount = history_amount(tx, &wallet_id);
+            query.min_amount.is_none_or(|min| amount >= min) && query.max_amount.is_none_or(|max| amount <= max)
+        }));
+        if exhausted || page.len() > limit {
+            break;
+        }
+    }
+    let next_cursor = if page.len() > limit {
+        page.truncate(limit);
+        page.last().map(|tx| tx.transaction_hash.clone())
+    } else {
+        None
+    };
+
+    let confirmations = DbOps::get_transaction_confirmations(&db, &page).await?;
+    let transactions: Vec<_> = page
         .iter()
         .map(|tx| {
             let tx_type = if tx.sender_wallet_id == wallet_id {
@@ -137,24 +766,63 @@ pub async fn get_history(
             } else {
                 "received"
             };
+            let counterparties = history_counterparties(tx, &wallet_id);
 
             json!({
                 "id": tx.id.map(|id| id.to_string()).unwrap_or_default(),
+                "hash": tx.transaction_hash,
                 "type": tx_type,
-                "amount": tx.amount,
-                "counterparty": if tx.sender_wallet_id == wallet_id {
-                    tx.recipient_wallet_id.clone()
-                } else {
-                    tx.sender_wallet_id.clone()
-                },
+                "amount": history_amount(tx, &wallet_id),
+                "counterparty": history_counterparty(&counterparties, query.counterparty.as_deref()),
+                "counterparties": counterparties,
+                "note": tx.note,
                 "timestamp": tx.timestamp,
                 "status": tx.status,
+                "confirmations": confirmations.get(&tx.transaction_hash).copied().unwrap_or(0),
                 "blockHash": tx.block_hash.clone().unwrap_or_default(),
             })
         })
         .collect();
 
-    Ok(HttpResponse::Ok().json(response))
+    Ok(HttpResponse::Ok().json(json!({
+        "transactions": transactions,
+        "next_cursor": next_cursor,
+    })))
+}
+
+// Amount a history entry shows for the wallet: a batch may pay it only part of its total
+pub fn history_amount(tx: &Transaction, wallet_id: &str) -> f64 {
+    if tx.sender_wallet_id != wallet_id && !tx.outputs.is_empty() {
+        tx.outputs.iter().filter(|o| o.wallet_id == wallet_id).map(|o| o.amount).sum()
+    } else {
+        tx.amount
+    }
+}
+
+// The other side of a transaction: its sender, or every recipient other than the sender's change
+fn history_counterparties(tx: &Transaction, wallet_id: &str) -> Vec<String> {
+    if tx.sender_wallet_id != wallet_id {
+        return vec![tx.sender_wallet_id.clone()];
+    }
+    let mut recipients: Vec<String> = Vec::new();
+    for output in &tx.outputs {
+        if output.wallet_id != wallet_id && !recipients.contains(&output.wallet_id) {
+            recipients.push(output.wallet_id.clone());
+        }
+    }
+    if recipients.is_empty() {
+        recipients.push(tx.recipient_wallet_id.clone());
+    }
+    recipients
+}
+
+// The counterparty the history was filtered by, otherwise the first one
+fn history_counterparty(counterparties: &[String], filter: Option<&str>) -> String {
+    filter
+        .and_then(|wanted| counterparties.iter().find(|c| c.as_str() == wanted))
+        .or_else(|| counterparties.first())
+        .cloned()
+        .unwrap_or_default()
 }
 
 pub async fn process_zakat_deduction(
@@ -182,6 +850,7 @@ pub async fn process_zakat_deduction(
             sender_wallet_id: wallet_id.to_string(),
             recipient_wallet_id: "ZAKAT_POOL".to_string(),
             amount: zakat_amount,
+            fee: 0.0,
             note: Some("Monthly Zakat Deduction (2.5%)".to_string()),
             timestamp: Utc::now(),
             sender_public_key: user.public_key.clone(),
@@ -192,7 +861,10 @@ pub async fn process_zakat_deduction(
             input_utxos: Vec::new(),
             output_utxo: output_utxo_id.clone(),
             change_utxo: None,
-            status: "confirmed".to_string(),
+            outputs: Vec::new(),
+            chain_id: None,
+            nonce: None,
+            status: TransactionStatus::Confirmed,
             block_hash: Some("zakat_block".to_string()),
         };
 
@@ -227,22 +899,72 @@ pub async fn get_transaction_details(
         .map_err(|e| ApiError::DatabaseError(e.to_string()))?
         .ok_or_else(|| ApiError::NotFound("Transaction not found".to_string()))?;
 
-    Ok(HttpResponse::Ok().json(tx))
+    let confirmations = DbOps::get_transaction_confirmations(&db, std::slice::from_ref(&tx))
+        .await?
+        .remove(&tx.transaction_hash)
+        .unwrap_or(0);
+    let mut details = serde_json::to_value(&tx).map_err(|e| ApiError::InternalError(e.to_string()))?;
+    details["confirmations"] = json!(confirmations);
+
+    Ok(HttpResponse::Ok().json(details))
 }
 
 pub async fn get_pending_transactions(
+    mempool: web::Data<Mempool>,
+) -> Result<HttpResponse, ApiError> {
+    Ok(HttpResponse::Ok().json(mempool.transactions()))
+}
+
+// Suggested fee rates and fees, from the fee rates paid in recent blocks and the current backlog
+pub async fn get_fee_estimate(
     db: web::Data<Database>,
+    mempool: web::Data<Mempool>,
 ) -> Result<HttpResponse, ApiError> {
-    let collection = db.collection::<Transaction>("transactions");
-    let mut cursor = collection
-        .find(mongodb::bson::doc! { "status": "pending" }, None)
-        .await
-        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+    let recent = DbOps::get_recent_blocks(&db, FEE_ESTIMATE_BLOCKS).await?;
+    Ok(HttpResponse::Ok().json(mempool.estimate_fees(&recent, MAX_BLOCK_SIZE)))
+}
 
-    let mut transactions = Vec::new();
-    while cursor.advance().await.map_err(|e| ApiError::DatabaseError(e.to_string()))? {
-        transactions.push(cursor.deserialize_current().map_err(|e| ApiError::DatabaseError(e.to_string()))?);
-    }
+pub async fn get_mempool_info(
+    mempool: web::Data<Mempool>,
+) -> Result<HttpResponse, ApiError> {
+    Ok(HttpResponse::Ok().json(mempool.info()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::coin_selection::{CoinSelector, LargestFirst};
+
+    // Amounts that do not add up exactly in binary floating point
+    #[test]
+    fn folded_change_leaves_no_change_output() {
+        let inputs: Vec<UTXO> = [0.1, 0.2, 0.7]
+            .iter()
+            .enumerate()
+            .map(|(i, &amount)| UTXO {
+                id: None,
+                utxo_id: format!("utxo-{}", i),
+                wallet_id: "sender".to_string(),
+                amount,
+                status: "unspent".to_string(),
+                block_hash: "block".to_string(),
+                transaction_hash: format!("tx-{}", i),
+                created_at: Utc::now(),
+            })
+            .collect();
+        let payments = vec![
+            Payment { recipient_wallet_id: "alice".to_string(), amount: 0.3 },
+            Payment { recipient_wallet_id: "bob".to_string(), amount: 0.6995 },
+        ];
+        let total: f64 = payments.iter().map(|p| p.amount).sum();
+
+        let selection = LargestFirst.select(&inputs, total, &|_, _| 0.0003, 0.001).unwrap();
+        assert_eq!(selection.inputs.len(), 3);
 
-    Ok(HttpResponse::Ok().json(transactions))
+        let tx = unsigned_transaction("sender", "", &payments, selection.fee, None, &selection.inputs, 1);
+        assert_eq!(tx.change_utxo, None);
+        assert_eq!(tx.outputs.len(), payments.len());
+        let change = selection.inputs.iter().map(|u| u.amount).sum::<f64>() - tx.amount - tx.fee;
+        assert_eq!(change, 0.0);
+    }
 }
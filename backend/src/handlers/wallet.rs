@@ -1,21 +1,30 @@
 use actix_web::{web, HttpResponse};
+use chrono::Utc;
 use mongodb::Database;
 use serde_json::json;
-use crate::models::BalanceResponse;
+use std::collections::HashSet;
+use crate::models::{BalanceResponse, ConsolidateRequest, ScheduledConsolidation, Transaction, TransactionStatus, UtxoResponse, UTXO};
 use crate::db::Database as DbOps;
 use crate::error::ApiError;
+use crate::address;
+use crate::blockchain::MAX_BLOCK_SIZE;
+use crate::coin_selection::{dust_threshold, min_confirmations};
+use crate::handlers::transactions::{consolidate_utxos, relay_transaction};
+use crate::mempool::{Mempool, FEE_ESTIMATE_BLOCKS, INPUT_SIZE};
+use crate::p2p::PeerManager;
 
 pub async fn get_wallet(
     db: web::Data<Database>,
     wallet_id: web::Path<String>,
 ) -> Result<HttpResponse, ApiError> {
-    let wallet_id = wallet_id.into_inner();
+    let wallet_id = address::parse(&wallet_id)?;
     
     let user = DbOps::find_user_by_wallet_id(&db, &wallet_id)
         .await?
         .ok_or_else(|| ApiError::NotFound("Wallet not found".to_string()))?;
 
     Ok(HttpResponse::Ok().json(json!({
+        "address": address::encode(&user.wallet_id),
         "wallet_id": user.wallet_id,
         "email": user.email,
         "full_name": user.full_name,
@@ -26,30 +35,220 @@ pub async fn get_wallet(
 
 pub async fn get_balance(
     db: web::Data<Database>,
+    mempool: web::Data<Mempool>,
     wallet_id: web::Path<String>,
 ) -> Result<HttpResponse, ApiError> {
-    let wallet_id = wallet_id.into_inner();
+    let wallet_id = address::parse(&wallet_id)?;
 
     let utxos = DbOps::get_utxos_for_wallet(&db, &wallet_id).await?;
     let balance: f64 = utxos.iter().map(|u| u.amount).sum();
 
-    let user = DbOps::find_user_by_wallet_id(&db, &wallet_id)
+    // Wallets without an account can hold funds too; they just have no zakat record
+    let zakat_deduction = DbOps::find_user_by_wallet_id(&db, &wallet_id)
         .await?
-        .ok_or_else(|| ApiError::NotFound("Wallet not found".to_string()))?;
+        .map_or(0.0, |user| user.zakat_deduction);
+
+    let confirmations = DbOps::get_utxo_confirmations(&db, &utxos).await?;
+    let depth = |u: &UTXO| confirmations.get(&u.utxo_id).copied().unwrap_or(0);
+    let min_confirmations = min_confirmations();
+
+    // This wallet's unconfirmed transactions; their unconfirmed outputs back to it are change, not income
+    let outgoing: Vec<Transaction> = mempool
+        .transactions()
+        .into_iter()
+        .filter(|tx| tx.sender_wallet_id == wallet_id)
+        .collect();
+    let own_hashes: HashSet<&str> = outgoing.iter().map(|tx| tx.transaction_hash.as_str()).collect();
+
+    let confirmed: f64 = utxos.iter().filter(|u| depth(u) > 0).map(|u| u.amount).sum();
+    let pending_incoming: f64 = utxos
+        .iter()
+        .filter(|u| depth(u) == 0 && !own_hashes.contains(u.transaction_hash.as_str()))
+        .map(|u| u.amount)
+        .sum();
+    let pending_outgoing: f64 = outgoing.iter().map(|tx| paid_to_others(tx) + tx.fee).sum();
+    let spendable: f64 = utxos
+        .iter()
+        .filter(|u| depth(u) >= min_confirmations)
+        .map(|u| u.amount)
+        .sum();
 
     let utxo_responses = utxos
         .iter()
-        .map(|u| crate::models::UtxoResponse {
+        .map(|u| UtxoResponse {
             id: u.utxo_id.clone(),
             amount: u.amount,
             status: u.status.clone(),
             block_hash: u.block_hash.clone(),
+            confirmations: depth(u),
         })
         .collect();
 
     Ok(HttpResponse::Ok().json(BalanceResponse {
         balance,
+        confirmed,
+        pending_incoming,
+        pending_outgoing,
+        spendable,
+        min_confirmations,
         utxos: utxo_responses,
-        zakat_deduction: user.zakat_deduction,
+        zakat_deduction,
     }))
 }
+
+// Amount a transaction pays to wallets other than its sender
+pub fn paid_to_others(tx: &Transaction) -> f64 {
+    if tx.outputs.is_empty() {
+        return if tx.recipient_wallet_id == tx.sender_wallet_id { 0.0 } else { tx.amount };
+    }
+    tx.outputs
+        .iter()
+        .filter(|o| o.wallet_id != tx.sender_wallet_id)
+        .map(|o| o.amount)
+        .sum()
+}
+
+// Current "slow" fee rate, which consolidations pay since they are never urgent
+async fn consolidation_fee_rate(db: &Database, mempool: &Mempool) -> Result<f64, ApiError> {
+    let recent = DbOps::get_recent_blocks(db, FEE_ESTIMATE_BLOCKS).await?;
+    Ok(mempool.estimate_fees(&recent, MAX_BLOCK_SIZE).fee_rates.slow)
+}
+
+// Merges small outputs into one now, or once fees fall to max_fee_rate
+pub async fn consolidate(
+    db: web::Data<Database>,
+    mempool: web::Data<Mempool>,
+    peers: web::Data<PeerManager>,
+    wallet_id: web::Path<String>,
+    req: web::Json<ConsolidateRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let wallet_id = address::parse(&wallet_id)?;
+    DbOps::find_user_by_wallet_id(&db, &wallet_id)
+        .await?
+        .ok_or_else(|| ApiError::NotFound("Wallet not found".to_string()))?;
+
+    let fee_rate = consolidation_fee_rate(&db, &mempool).await?;
+    if let Some(max_fee_rate) = req.max_fee_rate {
+        if fee_rate > max_fee_rate {
+            DbOps::save_scheduled_consolidation(
+                &db,
+                &ScheduledConsolidation {
+                    id: None,
+                    wallet_id: wallet_id.clone(),
+                    max_utxo_amount: req.max_utxo_amount,
+                    max_inputs: req.max_inputs,
+                    max_fee_rate,
+                    created_at: Utc::now(),
+                },
+            )
+            .await?;
+
+            return Ok(HttpResponse::Accepted().json(json!({
+                "status": "scheduled",
+                "wallet_id": wallet_id,
+                "fee_rate": fee_rate,
+                "max_fee_rate": max_fee_rate
+            })));
+        }
+    }
+
+    let transaction = consolidate_utxos(&db, &mempool, &wallet_id, req.max_utxo_amount, req.max_inputs, fee_rate).await?;
+    // Running now supersedes any earlier schedule
+    DbOps::delete_scheduled_consolidation(&db, &wallet_id).await?;
+    relay_transaction(db.clone(), peers, transaction.clone());
+
+    Ok(HttpResponse::Created().json(json!({
+        "status": TransactionStatus::InMempool,
+        "transaction_hash": transaction.transaction_hash,
+        "inputs": transaction.input_utxos.len(),
+        "amount": transaction.amount,
+        "fee": transaction.fee
+    })))
+}
+
+pub async fn cancel_consolidation(
+    db: web::Data<Database>,
+    wallet_id: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    if !DbOps::delete_scheduled_consolidation(&db, &address::parse(&wallet_id)?).await? {
+        return Err(ApiError::NotFound("No consolidation scheduled".to_string()));
+    }
+    Ok(HttpResponse::Ok().json(json!({ "status": "cancelled" })))
+}
+
+// Runs every scheduled consolidation whose fee limit the current rate meets. Each schedule
+// is attempted once; failures are logged and dropped.
+pub async fn run_scheduled_consolidations(
+    db: &Database,
+    mempool: &Mempool,
+    peers: &PeerManager,
+) -> Result<(), ApiError> {
+    let schedules = DbOps::get_scheduled_consolidations(db).await?;
+    if schedules.is_empty() {
+        return Ok(());
+    }
+
+    let fee_rate = consolidation_fee_rate(db, mempool).await?;
+    for schedule in schedules.iter().filter(|s| fee_rate <= s.max_fee_rate) {
+        DbOps::delete_scheduled_consolidation(db, &schedule.wallet_id).await?;
+        let result = consolidate_utxos(
+            db,
+            mempool,
+            &schedule.wallet_id,
+            schedule.max_utxo_amount,
+            schedule.max_inputs,
+            fee_rate,
+        )
+        .await;
+        match result {
+            Ok(tx) => {
+                log::info!("Consolidated {} outputs of wallet {}", tx.input_utxos.len(), schedule.wallet_id);
+                peers.broadcast_transaction(db, &tx).await;
+            }
+            Err(e) => log::warn!("Scheduled consolidation for {} failed: {}", schedule.wallet_id, e),
+        }
+    }
+    Ok(())
+}
+
+// Outputs worth less than the dust threshold or than the fee to spend them
+pub async fn get_dust_report(
+    db: web::Data<Database>,
+    mempool: web::Data<Mempool>,
+    wallet_id: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    let wallet_id = address::parse(&wallet_id)?;
+
+    let recent = DbOps::get_recent_blocks(&db, FEE_ESTIMATE_BLOCKS).await?;
+    let input_cost = mempool.estimate_fees(&recent, MAX_BLOCK_SIZE).fee_rates.normal * INPUT_SIZE as f64;
+    let limit = dust_threshold().max(input_cost);
+
+    let utxos = DbOps::get_utxos_for_wallet(&db, &wallet_id).await?;
+    let confirmations = DbOps::get_utxo_confirmations(&db, &utxos).await?;
+    let dust: Vec<UtxoResponse> = utxos
+        .iter()
+        .filter(|u| u.amount < limit)
+        .map(|u| UtxoResponse {
+            id: u.utxo_id.clone(),
+            amount: u.amount,
+            status: u.status.clone(),
+            block_hash: u.block_hash.clone(),
+            confirmations: confirmations.get(&u.utxo_id).copied().unwrap_or(0),
+        })
+        .collect();
+    let scheduled = DbOps::get_scheduled_consolidations(&db)
+        .await?
+        .into_iter()
+        .find(|s| s.wallet_id == wallet_id);
+
+    Ok(HttpResponse::Ok().json(json!({
+        "wallet_id": wallet_id,
+        "dust_threshold": dust_threshold(),
+        "input_cost": input_cost,
+        "unspent_outputs": utxos.len(),
+        "dust_outputs": dust.len(),
+        "dust_total": dust.iter().map(|u| u.amount).sum::<f64>(),
+        "outputs": dust,
+        "scheduled_consolidation": scheduled
+    })))
+}
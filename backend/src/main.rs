@@ -1,14 +1,20 @@
 mod models;
+mod address;
 mod handlers;
 mod blockchain;
+mod chain;
+mod coin_selection;
 mod crypto;
 mod db;
 mod middleware;
 mod error;
+mod mempool;
+mod p2p;
 
 use actix_web::{web, App, HttpServer, middleware::Logger};
 use dotenv::dotenv;
 use std::env;
+use std::sync::Arc;
 use mongodb::Client;
 
 #[actix_web::main]
@@ -23,13 +29,110 @@ async fn main() -> std::io::Result<()> {
         .await
         .expect("Failed to create MongoDB client");
 
-    let db = client.database("crypto_wallet");
+    // Separate database names let several nodes share one MongoDB server
+    let db_name = env::var("DB_NAME").unwrap_or_else(|_| "crypto_wallet".to_string());
+    let db = client.database(&db_name);
 
-    log::info!("Starting CryptoWallet Backend on 0.0.0.0:3001");
+    db::Database::init_chain(&db)
+        .await
+        .expect("Failed to initialize blockchain");
+    db::Database::create_indexes(&db)
+        .await
+        .expect("Failed to create database indexes");
+    let rekeyed = db::Database::migrate_legacy_keys(&db)
+        .await
+        .expect("Failed to migrate legacy wallet keys");
+    if rekeyed > 0 {
+        log::info!("Re-keyed {} legacy wallets to Ed25519", rekeyed);
+    }
+
+    // `crypto-wallet repair-chain-state` rebuilds the state document from the blocks and exits
+    if env::args().nth(1).as_deref() == Some("repair-chain-state") {
+        let state = db::Database::repair_blockchain_state(&db)
+            .await
+            .expect("Failed to repair blockchain state");
+        log::info!(
+            "Repaired blockchain state: tip {} at length {}",
+            state.latest_block_hash,
+            state.chain_length
+        );
+        return Ok(());
+    }
+
+    let port: u16 = env::var("PORT")
+        .ok()
+        .and_then(|p| p.parse().ok())
+        .unwrap_or(3001);
+    let node_url = env::var("NODE_URL").unwrap_or_else(|_| format!("http://127.0.0.1:{}", port));
+    let seeds: Vec<String> = env::var("PEERS")
+        .unwrap_or_default()
+        .split(',')
+        .map(|p| p.trim().trim_end_matches('/').to_string())
+        .filter(|p| !p.is_empty())
+        .collect();
+
+    let mempool = Arc::new(mempool::Mempool::new(
+        env::var("MEMPOOL_MAX_BYTES")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(mempool::DEFAULT_MAX_MEMPOOL_BYTES),
+        env::var("MEMPOOL_EXPIRY_HOURS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(mempool::DEFAULT_MEMPOOL_EXPIRY_HOURS),
+        env::var("MIN_RELAY_FEE_RATE")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(mempool::DEFAULT_MIN_RELAY_FEE_RATE),
+    ));
+    match mempool.load(&db).await {
+        Ok(count) => log::info!("Loaded {} pending transactions into the mempool", count),
+        Err(e) => log::warn!("Failed to load mempool: {}", e),
+    }
+
+    // Stale transactions are also dropped on every insert and block template
+    let expiry_db = db.clone();
+    let expiry_pool = mempool.clone();
+    actix_web::rt::spawn(async move {
+        let mut interval = actix_web::rt::time::interval(std::time::Duration::from_secs(600));
+        loop {
+            interval.tick().await;
+            if let Err(e) = expiry_pool.expire(&expiry_db).await {
+                log::warn!("Mempool expiry failed: {}", e);
+            }
+        }
+    });
+
+    let peers = web::Data::new(p2p::PeerManager::new(node_url, mempool.clone()));
+    peers.start(&db, &seeds).await;
+
+    // Scheduled consolidations run once the fee rate falls to their limit
+    let consolidation_db = db.clone();
+    let consolidation_pool = mempool.clone();
+    let consolidation_peers = peers.clone();
+    actix_web::rt::spawn(async move {
+        let mut interval = actix_web::rt::time::interval(std::time::Duration::from_secs(300));
+        loop {
+            interval.tick().await;
+            if let Err(e) = handlers::wallet::run_scheduled_consolidations(
+                &consolidation_db,
+                &consolidation_pool,
+                &consolidation_peers,
+            )
+            .await
+            {
+                log::warn!("Scheduled consolidations failed: {}", e);
+            }
+        }
+    });
+
+    log::info!("Starting CryptoWallet Backend on 0.0.0.0:{}", port);
 
     HttpServer::new(move || {
         App::new()
             .app_data(web::Data::new(db.clone()))
+            .app_data(web::Data::from(mempool.clone()))
+            .app_data(peers.clone())
             .wrap(Logger::default())
             .wrap(
                 actix_web::middleware::DefaultHeaders::new()
@@ -40,29 +143,59 @@ async fn main() -> std::io::Result<()> {
             .route("/api/auth/register", web::post().to(handlers::auth::register))
             .route("/api/auth/login", web::post().to(handlers::auth::login))
             .route("/api/auth/verify-otp", web::post().to(handlers::auth::verify_otp))
+            .route("/api/auth/restore", web::post().to(handlers::auth::restore))
             
             // Wallet routes
             .route("/api/wallet/{wallet_id}", web::get().to(handlers::wallet::get_wallet))
             .route("/api/wallet/{wallet_id}/balance", web::get().to(handlers::wallet::get_balance))
+            .route("/api/wallet/{wallet_id}/dust", web::get().to(handlers::wallet::get_dust_report))
+            .route("/api/wallet/{wallet_id}/statement", web::get().to(handlers::statement::get_statement))
+            .route("/api/wallet/{wallet_id}/beneficiaries", web::get().to(handlers::beneficiaries::list_beneficiaries))
+            .route("/api/wallet/{wallet_id}/beneficiaries", web::post().to(handlers::beneficiaries::add_beneficiary))
+            .route("/api/wallet/{wallet_id}/beneficiaries/{beneficiary_wallet_id}", web::put().to(handlers::beneficiaries::rename_beneficiary))
+            .route("/api/wallet/{wallet_id}/beneficiaries/{beneficiary_wallet_id}", web::delete().to(handlers::beneficiaries::remove_beneficiary))
+            .route("/api/wallet/{wallet_id}/consolidate", web::post().to(handlers::wallet::consolidate))
+            .route("/api/wallet/{wallet_id}/consolidate", web::delete().to(handlers::wallet::cancel_consolidation))
             
             // Transaction routes
             .route("/api/transactions/send", web::post().to(handlers::transactions::send_money))
+            .route("/api/transactions/prepare", web::post().to(handlers::transactions::prepare_transaction))
+            .route("/api/transactions/submit", web::post().to(handlers::transactions::submit_transaction))
+            .route("/api/transactions/send-to-beneficiary", web::post().to(handlers::beneficiaries::send_to_beneficiary))
+            .route("/api/transactions/batch", web::post().to(handlers::transactions::send_batch))
             .route("/api/transactions/history/{wallet_id}", web::get().to(handlers::transactions::get_history))
+            .route("/api/transactions/fee-estimate", web::get().to(handlers::transactions::get_fee_estimate))
+            .route("/api/transactions/{tx_hash}/replace", web::post().to(handlers::transactions::replace_transaction))
+            .route("/api/transactions/{tx_hash}/cancel", web::post().to(handlers::transactions::cancel_transaction))
             .route("/api/transactions/{tx_hash}", web::get().to(handlers::transactions::get_transaction_details))
             .route("/api/transactions/pending/list", web::get().to(handlers::transactions::get_pending_transactions))
+            .route("/api/mempool", web::get().to(handlers::transactions::get_mempool_info))
             
             // Blockchain routes
             .route("/api/blockchain/blocks", web::get().to(handlers::blockchain::get_blocks))
+            .route("/api/blockchain/blocks", web::post().to(handlers::blockchain::submit_block))
+            .route("/api/blockchain/headers", web::get().to(handlers::blockchain::get_headers))
+            .route("/api/blockchain/headers/{block_hash}", web::get().to(handlers::blockchain::get_header))
+            .route("/api/blockchain/proof/verify", web::post().to(handlers::blockchain::verify_merkle_proof))
+            .route("/api/blockchain/proof/{tx_hash}", web::get().to(handlers::blockchain::get_merkle_proof))
             .route("/api/blockchain/mine", web::post().to(handlers::blockchain::mine_block))
             .route("/api/blockchain/status", web::get().to(handlers::blockchain::get_status))
             .route("/api/blockchain/mine-pending", web::post().to(handlers::blockchain::mine_pending_transactions))
             .route("/api/blockchain/validate", web::get().to(handlers::blockchain::validate_blockchain))
             .route("/api/blockchain/info", web::get().to(handlers::blockchain::get_chain_info))
             
+            // Peer-to-peer routes
+            .route("/api/p2p/version", web::post().to(handlers::p2p::version))
+            .route("/api/p2p/peers", web::get().to(handlers::p2p::get_peers))
+            .route("/api/p2p/blocks", web::post().to(handlers::p2p::receive_block))
+            .route("/api/p2p/transactions", web::post().to(handlers::p2p::receive_transaction))
+            .route("/api/p2p/getheaders", web::post().to(handlers::p2p::get_headers))
+            .route("/api/p2p/getdata", web::post().to(handlers::p2p::get_data))
+
             // Zakat operations
             .route("/api/zakat/process/{wallet_id}", web::post().to(handlers::transactions::process_zakat_deduction))
     })
-    .bind("0.0.0.0:3001")?
+    .bind(("0.0.0.0", port))?
     .run()
     .await
 }
@@ -0,0 +1,832 @@
+use crate::blockchain::{Blockchain, CURRENT_BLOCK_VERSION};
+use crate::chain::ChainManager;
+use crate::db::Database as DbOps;
+use crate::error::{ApiError, ValidationError};
+use crate::models::{Block, FeeEstimate, FeeRates, Transaction, TransactionStatus, UTXO};
+use chrono::{DateTime, Duration, Utc};
+use mongodb::Database;
+use serde::Serialize;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::sync::Mutex;
+
+pub const DEFAULT_MAX_MEMPOOL_BYTES: usize = 5_000_000;
+pub const DEFAULT_MEMPOOL_EXPIRY_HOURS: i64 = 72;
+// Fee per byte of serialized transaction below which transactions are not relayed or mined
+pub const DEFAULT_MIN_RELAY_FEE_RATE: f64 = 0.00001;
+// Serialized size of a transaction without inputs, outputs or note, plus the cost of each, rounded up
+const BASE_TRANSACTION_SIZE: usize = 750;
+pub const INPUT_SIZE: usize = 40;
+const OUTPUT_SIZE: usize = 150;
+const REPLACEMENT_FEE_TOLERANCE: f64 = 1e-9;
+// Recent main-chain blocks sampled for fee estimates
+pub const FEE_ESTIMATE_BLOCKS: i64 = 20;
+
+#[derive(Debug, Clone)]
+pub struct MempoolEntry {
+    pub transaction: Transaction,
+    pub outputs: Vec<UTXO>,
+    pub fee: f64,
+    pub size: usize,
+    pub added_at: DateTime<Utc>,
+}
+
+impl MempoolEntry {
+    pub fn fee_rate(&self) -> f64 {
+        self.fee / self.size.max(1) as f64
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct MempoolInfo {
+    pub transactions: usize,
+    pub bytes: usize,
+    pub max_bytes: usize,
+    pub min_fee_rate: f64,
+    pub expiry_hours: i64,
+}
+
+#[derive(Default)]
+struct MempoolState {
+    entries: HashMap<String, MempoolEntry>,
+    // UTXO id -> hash of the pooled transaction spending it
+    spent_by: HashMap<String, String>,
+    // UTXO id -> hash of the pooled transaction creating it
+    created_by: HashMap<String, String>,
+    bytes: usize,
+}
+
+impl MempoolState {
+    fn insert(&mut self, entry: MempoolEntry) {
+        let tx = &entry.transaction;
+        for input in &tx.input_utxos {
+            self.spent_by.insert(input.clone(), tx.transaction_hash.clone());
+        }
+        for output in &entry.outputs {
+            self.created_by.insert(output.utxo_id.clone(), tx.transaction_hash.clone());
+        }
+        self.bytes += entry.size;
+        self.entries.insert(tx.transaction_hash.clone(), entry);
+    }
+
+    fn remove(&mut self, tx_hash: &str) -> Option<MempoolEntry> {
+        let entry = self.entries.remove(tx_hash)?;
+        for input in &entry.transaction.input_utxos {
+            if self.spent_by.get(input).map(String::as_str) == Some(tx_hash) {
+                self.spent_by.remove(input);
+            }
+        }
+        for output in &entry.outputs {
+            self.created_by.remove(&output.utxo_id);
+        }
+        self.bytes -= entry.size;
+        Some(entry)
+    }
+
+    // Pooled transactions spending this one's outputs
+    fn children(&self, tx_hash: &str) -> Vec<String> {
+        self.entries
+            .get(tx_hash)
+            .map(|entry| {
+                entry
+                    .outputs
+                    .iter()
+                    .filter_map(|o| self.spent_by.get(&o.utxo_id).cloned())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    // Pooled transactions whose outputs this one spends
+    fn parents(&self, tx: &Transaction) -> Vec<String> {
+        tx.input_utxos
+            .iter()
+            .filter_map(|input| self.created_by.get(input).cloned())
+            .collect()
+    }
+
+    // The transaction itself and everything that depends on it
+    fn descendants(&self, tx_hash: &str) -> HashSet<String> {
+        let mut found = HashSet::new();
+        let mut queue = vec![tx_hash.to_string()];
+        while let Some(hash) = queue.pop() {
+            if found.insert(hash.clone()) {
+                queue.extend(self.children(&hash));
+            }
+        }
+        found
+    }
+
+    fn remove_with_descendants(&mut self, tx_hash: &str) -> Vec<MempoolEntry> {
+        self.descendants(tx_hash)
+            .iter()
+            .filter_map(|hash| self.remove(hash))
+            .collect()
+    }
+}
+
+// Unconfirmed transactions waiting to be mined. The database keeps the durable copy; the pool
+// holds the fee and dependency information needed to order, limit and expire them.
+pub struct Mempool {
+    state: Mutex<MempoolState>,
+    max_bytes: usize,
+    expiry: Duration,
+    min_fee_rate: f64,
+}
+
+impl Mempool {
+    pub fn new(max_bytes: usize, expiry_hours: i64, min_fee_rate: f64) -> Self {
+        Mempool {
+            state: Mutex::new(MempoolState::default()),
+            max_bytes,
+            expiry: Duration::hours(expiry_hours),
+            min_fee_rate,
+        }
+    }
+
+    pub fn contains(&self, tx_hash: &str) -> bool {
+        self.state.lock().unwrap().entries.contains_key(tx_hash)
+    }
+
+    pub fn info(&self) -> MempoolInfo {
+        let state = self.state.lock().unwrap();
+        MempoolInfo {
+            transactions: state.entries.len(),
+            bytes: state.bytes,
+            max_bytes: self.max_bytes,
+            min_fee_rate: state.entries.values().map(MempoolEntry::fee_rate).reduce(f64::min).unwrap_or(0.0),
+            expiry_hours: self.expiry.num_hours(),
+        }
+    }
+
+    // Pooled transactions, highest fee rate first
+    pub fn transactions(&self) -> Vec<Transaction> {
+        let state = self.state.lock().unwrap();
+        let mut entries: Vec<&MempoolEntry> = state.entries.values().collect();
+        entries.sort_by(|a, b| b.fee_rate().total_cmp(&a.fee_rate()).then(a.added_at.cmp(&b.added_at)));
+        entries.into_iter().map(|e| e.transaction.clone()).collect()
+    }
+
+    pub fn transaction_size(tx: &Transaction) -> usize {
+        serde_json::to_vec(tx).map(|bytes| bytes.len()).unwrap_or(0)
+    }
+
+    // Upper bound on the size of a transaction before it is built, so its fee can be chosen up front
+    pub fn estimated_transaction_size(inputs: usize, outputs: usize, note: Option<&str>) -> usize {
+        BASE_TRANSACTION_SIZE + inputs * INPUT_SIZE + outputs * OUTPUT_SIZE + note.map_or(0, str::len)
+    }
+
+    fn fee(tx: &Transaction, inputs: &HashMap<String, UTXO>, outputs: &[UTXO]) -> f64 {
+        let input_total: f64 = tx.input_utxos.iter().filter_map(|id| inputs.get(id)).map(|u| u.amount).sum();
+        let output_total: f64 = outputs.iter().map(|u| u.amount).sum();
+        input_total - output_total
+    }
+
+    // Validates a new transaction against our view of unspent outputs, makes room for it and stores it.
+    // Returns false if the transaction is already known.
+    pub async fn add_transaction(&self, db: &Database, tx: Transaction, outputs: Vec<UTXO>) -> Result<bool, ApiError> {
+        if self.contains(&tx.transaction_hash) || DbOps::get_transaction_by_hash(db, &tx.transaction_hash).await?.is_some() {
+            return Ok(false);
+        }
+
+        self.expire(db).await?;
+
+        // Inputs must be unspent in the database, which covers confirmed outputs and outputs of pooled
+        // parents. Inputs claimed by a pooled transaction are also allowed; the fee rules decide
+        // whether this transaction may replace it.
+        let unspent: HashMap<String, UTXO> = DbOps::get_utxos_by_ids(db, &tx.input_utxos)
+            .await?
+            .into_iter()
+            .filter(|u| u.status == "unspent" || (u.status == "spent" && self.is_spent_in_pool(&u.utxo_id)))
+            .map(|u| (u.utxo_id.clone(), u))
+            .collect();
+        let outputs: HashMap<String, UTXO> = outputs
+            .into_iter()
+            .filter(|u| u.transaction_hash == tx.transaction_hash)
+            .map(|u| (u.utxo_id.clone(), u))
+            .collect();
+        let fee = Blockchain::validate_transaction(&tx, &unspent, &outputs, CURRENT_BLOCK_VERSION)?;
+        let mut confirmed_nonces = HashMap::new();
+        if let Some(last) = DbOps::get_last_nonce(db, &tx.sender_wallet_id, true).await? {
+            confirmed_nonces.insert(tx.sender_wallet_id.clone(), last);
+        }
+        Blockchain::check_replay_protection(&tx, &confirmed_nonces, CURRENT_BLOCK_VERSION)?;
+
+        let mut tx = tx;
+        tx.id = None;
+        tx.status = TransactionStatus::InMempool;
+        tx.block_hash = None;
+        let entry = MempoolEntry {
+            size: Self::transaction_size(&tx),
+            transaction: tx.clone(),
+            outputs: outputs.into_values().collect(),
+            fee,
+            // Arrival time, not the sender's own timestamp, so expiry and tie-breaks cannot be gamed
+            added_at: Utc::now(),
+        };
+        if entry.fee_rate() < self.min_fee_rate {
+            return Err(ApiError::BadRequest(format!(
+                "Fee {} is below the minimum relay fee of {} for {} bytes",
+                entry.fee,
+                self.min_fee_rate * entry.size as f64,
+                entry.size
+            )));
+        }
+
+        let (replaced, evicted) = {
+            let mut state = self.state.lock().unwrap();
+            if state.entries.contains_key(&tx.transaction_hash) {
+                return Ok(false);
+            }
+
+            let conflicts = Self::direct_conflicts(&state, &tx);
+            // A pooled transaction's nonce may only be reused by a replacement of it
+            let nonce_taken = state.entries.values().any(|e| {
+                e.transaction.sender_wallet_id == tx.sender_wallet_id
+                    && e.transaction.nonce == tx.nonce
+                    && !conflicts.contains(&e.transaction.transaction_hash)
+            });
+            if nonce_taken {
+                return Err(ApiError::BadRequest(format!(
+                    "Nonce {} is already used by a pending transaction",
+                    tx.nonce.unwrap_or_default()
+                )));
+            }
+            self.check_replacement(&state, &entry, &conflicts)?;
+            let replaced: Vec<MempoolEntry> = conflicts
+                .iter()
+                .flat_map(|hash| state.remove_with_descendants(hash))
+                .collect();
+
+            let evicted = match self.make_room(&mut state, &entry) {
+                Ok(evicted) => evicted,
+                Err(e) => {
+                    for old in replaced {
+                        state.insert(old);
+                    }
+                    return Err(e);
+                }
+            };
+            state.insert(entry.clone());
+
+            // Only the directly conflicting transactions count as replaced; their descendants are orphaned
+            let replaced: Vec<Transaction> = replaced
+                .into_iter()
+                .map(|e| e.transaction)
+                .filter(|t| conflicts.contains(&t.transaction_hash))
+                .collect();
+            (replaced, evicted)
+        };
+
+        // Release the replaced transactions' inputs before this one claims its own
+        if !replaced.is_empty() {
+            let hashes: Vec<String> = replaced.iter().map(|t| t.transaction_hash.clone()).collect();
+            log::info!("Transaction {} replaces {}", tx.transaction_hash, hashes.join(", "));
+            ChainManager::drop_transactions(db, replaced, TransactionStatus::Replaced, &HashSet::new()).await?;
+            DbOps::remove_pending_transactions(db, &hashes).await?;
+        }
+
+        for output in &entry.outputs {
+            let mut output = output.clone();
+            output.status = "unspent".to_string();
+            output.block_hash = "pending".to_string();
+            DbOps::insert_utxo_if_missing(db, &output).await?;
+        }
+        DbOps::create_transaction(db, &tx).await?;
+        DbOps::set_mempool_added_at(db, &tx.transaction_hash, entry.added_at).await?;
+        DbOps::add_pending_transaction(db, &tx.transaction_hash).await?;
+        DbOps::set_utxos_status(db, &tx.input_utxos, "spent").await?;
+
+        if !evicted.is_empty() {
+            log::info!("Evicted {} low-fee transactions to admit {}", evicted.len(), tx.transaction_hash);
+            Self::drop_from_database(db, evicted).await?;
+        }
+        Ok(true)
+    }
+
+    fn is_spent_in_pool(&self, utxo_id: &str) -> bool {
+        self.state.lock().unwrap().spent_by.contains_key(utxo_id)
+    }
+
+    // Pooled transactions spending any of the same inputs
+    fn direct_conflicts(state: &MempoolState, tx: &Transaction) -> HashSet<String> {
+        tx.input_utxos
+            .iter()
+            .filter_map(|id| state.spent_by.get(id).cloned())
+            .collect()
+    }
+
+    // Total fee of the given transactions and everything descending from them
+    fn replaced_fees(state: &MempoolState, conflicts: &HashSet<String>) -> f64 {
+        conflicts
+            .iter()
+            .flat_map(|hash| state.descendants(hash))
+            .collect::<HashSet<_>>()
+            .iter()
+            .filter_map(|hash| state.entries.get(hash))
+            .map(|e| e.fee)
+            .sum()
+    }
+
+    // Smallest fee a transaction of `size` bytes must pay to replace whatever in the pool
+    // spends the same inputs; just the minimum relay fee if nothing does
+    pub fn replacement_fee(&self, tx: &Transaction, size: usize) -> f64 {
+        let state = self.state.lock().unwrap();
+        let conflicts = Self::direct_conflicts(&state, tx);
+        Self::replaced_fees(&state, &conflicts) + self.min_fee_rate * size as f64
+    }
+
+    // Replace-by-fee: a transaction spending inputs already claimed in the pool replaces those
+    // transactions, and their descendants, if it pays for all of them plus its own relay and
+    // has a higher fee rate than each transaction it directly conflicts with
+    fn check_replacement(
+        &self,
+        state: &MempoolState,
+        entry: &MempoolEntry,
+        conflicts: &HashSet<String>,
+    ) -> Result<(), ApiError> {
+        if conflicts.is_empty() {
+            return Ok(());
+        }
+
+        let replaced: HashSet<String> = conflicts.iter().flat_map(|hash| state.descendants(hash)).collect();
+        if let Some(parent) = state.parents(&entry.transaction).iter().find(|p| replaced.contains(*p)) {
+            return Err(ValidationError::DoubleSpend {
+                tx: entry.transaction.transaction_hash.clone(),
+                utxo: parent.clone(),
+            }
+            .into());
+        }
+
+        let required = Self::replaced_fees(state, conflicts) + self.min_fee_rate * entry.size as f64;
+        if entry.fee + REPLACEMENT_FEE_TOLERANCE < required {
+            return Err(ApiError::BadRequest(format!(
+                "Replacement fee {} is too low; at least {} is required",
+                entry.fee, required
+            )));
+        }
+        for hash in conflicts {
+            if let Some(old) = state.entries.get(hash) {
+                if entry.fee_rate() <= old.fee_rate() {
+                    return Err(ApiError::BadRequest(format!(
+                        "Replacement fee rate must exceed that of transaction {}",
+                        hash
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Evicts the lowest fee-rate transactions, with their descendants, until the entry fits.
+    // An entry paying less than everything it would displace is refused instead.
+    fn make_room(&self, state: &mut MempoolState, entry: &MempoolEntry) -> Result<Vec<Transaction>, ApiError> {
+        let parents = state.parents(&entry.transaction);
+        let mut candidates: Vec<&MempoolEntry> = state.entries.values().collect();
+        candidates.sort_by(|a, b| a.fee_rate().total_cmp(&b.fee_rate()));
+
+        let mut doomed = HashSet::new();
+        let mut freed = 0;
+        for candidate in candidates {
+            if state.bytes - freed + entry.size <= self.max_bytes || candidate.fee_rate() >= entry.fee_rate() {
+                break;
+            }
+            // Evicting an ancestor of the new entry would leave it spending missing outputs
+            let descendants = state.descendants(&candidate.transaction.transaction_hash);
+            if descendants.iter().any(|hash| parents.contains(hash)) {
+                continue;
+            }
+            for hash in descendants {
+                if let Some(size) = state.entries.get(&hash).map(|e| e.size) {
+                    if doomed.insert(hash) {
+                        freed += size;
+                    }
+                }
+            }
+        }
+
+        if state.bytes - freed + entry.size > self.max_bytes {
+            return Err(ApiError::BadRequest(format!(
+                "Mempool is full; fee rate {:.8} per byte is too low",
+                entry.fee_rate()
+            )));
+        }
+        Ok(doomed.iter().filter_map(|hash| state.remove(hash)).map(|e| e.transaction).collect())
+    }
+
+    // Drops transactions older than the expiry window, along with their descendants
+    pub async fn expire(&self, db: &Database) -> Result<usize, ApiError> {
+        let cutoff = Utc::now() - self.expiry;
+        let expired = {
+            let mut state = self.state.lock().unwrap();
+            let stale: Vec<String> = state
+                .entries
+                .values()
+                .filter(|e| e.added_at < cutoff)
+                .map(|e| e.transaction.transaction_hash.clone())
+                .collect();
+            stale
+                .iter()
+                .flat_map(|hash| state.remove_with_descendants(hash))
+                .map(|e| e.transaction)
+                .collect::<Vec<_>>()
+        };
+
+        let count = expired.len();
+        if count > 0 {
+            log::info!("Expired {} transactions from the mempool", count);
+            Self::drop_from_database(db, expired).await?;
+        }
+        Ok(count)
+    }
+
+    // Transactions the pool gives up on are rejected and release their inputs
+    async fn drop_from_database(db: &Database, transactions: Vec<Transaction>) -> Result<(), ApiError> {
+        let hashes: Vec<String> = transactions.iter().map(|tx| tx.transaction_hash.clone()).collect();
+        ChainManager::drop_transactions(db, transactions, TransactionStatus::Rejected, &HashSet::new()).await?;
+        DbOps::remove_pending_transactions(db, &hashes).await
+    }
+
+    // Picks transactions for a block template by fee rate, always placing parents before children.
+    // Sizes include one separator byte per transaction, matching the JSON array the block size is measured on.
+    pub fn select_for_block(&self, max_bytes: usize) -> Vec<Transaction> {
+        let state = self.state.lock().unwrap();
+        let mut remaining: Vec<&MempoolEntry> = state.entries.values().collect();
+        remaining.sort_by(|a, b| b.fee_rate().total_cmp(&a.fee_rate()).then(a.added_at.cmp(&b.added_at)));
+
+        // Each sender's nonces must appear in increasing order, so only its lowest remaining one is ready
+        let mut nonces: HashMap<&str, BTreeSet<u64>> = HashMap::new();
+        for entry in &remaining {
+            if let Some(nonce) = entry.transaction.nonce {
+                nonces.entry(entry.transaction.sender_wallet_id.as_str()).or_default().insert(nonce);
+            }
+        }
+
+        let mut included = HashSet::new();
+        let mut selected = Vec::new();
+        let mut bytes = 1;
+        loop {
+            let next = remaining.iter().position(|e| {
+                let tx = &e.transaction;
+                let nonce_ready = tx.nonce.is_none_or(|n| {
+                    nonces.get(tx.sender_wallet_id.as_str()).and_then(|set| set.first()) == Some(&n)
+                });
+                bytes + e.size < max_bytes
+                    && nonce_ready
+                    && state.parents(tx).iter().all(|p| included.contains(p))
+            });
+            let Some(position) = next else { break };
+
+            let entry = remaining.remove(position);
+            if let (Some(nonce), Some(set)) = (
+                entry.transaction.nonce,
+                nonces.get_mut(entry.transaction.sender_wallet_id.as_str()),
+            ) {
+                set.remove(&nonce);
+            }
+            bytes += entry.size + 1;
+            included.insert(entry.transaction.transaction_hash.clone());
+            selected.push(entry.transaction.clone());
+        }
+        selected
+    }
+
+    // Mirrors a connected block: its transactions leave the pool, and so does anything conflicting with them.
+    // Returns the pooled transactions, with descendants, whose nonce the block has made stale.
+    pub fn remove_for_block(&self, block: &Block) -> Vec<Transaction> {
+        let mut state = self.state.lock().unwrap();
+        for tx in &block.transactions {
+            state.remove(&tx.transaction_hash);
+        }
+        for tx in &block.transactions {
+            for input in &tx.input_utxos {
+                if let Some(conflict) = state.spent_by.get(input).cloned() {
+                    state.remove_with_descendants(&conflict);
+                }
+            }
+        }
+
+        let mut used: HashMap<&str, u64> = HashMap::new();
+        for tx in &block.transactions {
+            if let Some(nonce) = tx.nonce {
+                let last = used.entry(tx.sender_wallet_id.as_str()).or_insert(nonce);
+                *last = (*last).max(nonce);
+            }
+        }
+        let stale: Vec<String> = state
+            .entries
+            .values()
+            .filter(|e| {
+                let tx = &e.transaction;
+                matches!((tx.nonce, used.get(tx.sender_wallet_id.as_str())), (Some(n), Some(&last)) if n <= last)
+            })
+            .map(|e| e.transaction.transaction_hash.clone())
+            .collect();
+        stale
+            .iter()
+            .flat_map(|hash| state.remove_with_descendants(hash))
+            .map(|e| e.transaction)
+            .collect()
+    }
+
+    // Drops pooled transactions spending the given outputs, and their descendants
+    pub fn remove_spenders(&self, utxo_ids: &[String]) -> Vec<Transaction> {
+        let mut state = self.state.lock().unwrap();
+        let spenders: Vec<String> = utxo_ids.iter().filter_map(|id| state.spent_by.get(id).cloned()).collect();
+        spenders
+            .iter()
+            .flat_map(|hash| state.remove_with_descendants(hash))
+            .map(|e| e.transaction)
+            .collect()
+    }
+
+    // Suggests fee rates from what recent blocks actually included: the 90th, 50th and 10th
+    // percentile for fast, normal and slow. When the pool holds more than a block's worth,
+    // "fast" must also outbid the entry that would just miss the next block.
+    pub fn estimate_fees(&self, recent_blocks: &[Block], max_block_bytes: usize) -> FeeEstimate {
+        let mut rates: Vec<f64> = recent_blocks
+            .iter()
+            .flat_map(|block| block.transactions.iter())
+            .filter(|tx| !Blockchain::is_coinbase(tx))
+            .map(|tx| tx.fee / Self::transaction_size(tx).max(1) as f64)
+            .collect();
+        rates.sort_by(|a, b| a.total_cmp(b));
+
+        let percentile = |p: f64| -> f64 {
+            if rates.is_empty() {
+                return self.min_fee_rate;
+            }
+            let index = ((rates.len() - 1) as f64 * p).round() as usize;
+            rates[index].max(self.min_fee_rate)
+        };
+        let mut fee_rates = FeeRates {
+            fast: percentile(0.9),
+            normal: percentile(0.5),
+            slow: percentile(0.1),
+        };
+
+        {
+            let state = self.state.lock().unwrap();
+            let mut entries: Vec<&MempoolEntry> = state.entries.values().collect();
+            entries.sort_by(|a, b| b.fee_rate().total_cmp(&a.fee_rate()));
+            let mut bytes = 0;
+            for entry in entries {
+                bytes += entry.size + 1;
+                if bytes > max_block_bytes {
+                    fee_rates.fast = fee_rates.fast.max(entry.fee_rate() + self.min_fee_rate);
+                    break;
+                }
+            }
+        }
+        fee_rates.normal = fee_rates.normal.min(fee_rates.fast);
+        fee_rates.slow = fee_rates.slow.min(fee_rates.normal);
+
+        // One input, one payment and change
+        let typical_size = Self::estimated_transaction_size(1, 2, None);
+        let typical = typical_size as f64;
+        FeeEstimate {
+            min_relay_fee_rate: self.min_fee_rate,
+            fees: FeeRates {
+                fast: fee_rates.fast * typical,
+                normal: fee_rates.normal * typical,
+                slow: fee_rates.slow * typical,
+            },
+            fee_rates,
+            typical_size,
+            blocks_sampled: recent_blocks.len(),
+            transactions_sampled: rates.len(),
+        }
+    }
+
+    // Puts transactions back without revalidating, e.g. after their block was disconnected
+    pub async fn restore(&self, db: &Database, transactions: Vec<Transaction>) -> Result<(), ApiError> {
+        if transactions.is_empty() {
+            return Ok(());
+        }
+
+        let hashes: Vec<String> = transactions.iter().map(|tx| tx.transaction_hash.clone()).collect();
+        let inputs: Vec<String> = transactions.iter().flat_map(|tx| tx.input_utxos.iter().cloned()).collect();
+        let outputs = DbOps::get_utxos_by_transaction_hashes(db, &hashes).await?;
+        let added_at = DbOps::get_mempool_added_at(db, &hashes).await?;
+        let inputs: HashMap<String, UTXO> = DbOps::get_utxos_by_ids(db, &inputs)
+            .await?
+            .into_iter()
+            .map(|u| (u.utxo_id.clone(), u))
+            .collect();
+
+        let mut state = self.state.lock().unwrap();
+        for tx in transactions {
+            let outputs: Vec<UTXO> = outputs
+                .iter()
+                .filter(|u| u.transaction_hash == tx.transaction_hash)
+                .cloned()
+                .collect();
+            let entry = MempoolEntry {
+                fee: Self::fee(&tx, &inputs, &outputs),
+                size: Self::transaction_size(&tx),
+                added_at: added_at.get(&tx.transaction_hash).copied().unwrap_or_else(Utc::now),
+                transaction: tx,
+                outputs,
+            };
+            state.remove(&entry.transaction.transaction_hash);
+            state.insert(entry);
+        }
+        Ok(())
+    }
+
+    // Rebuilds the pool from the pending transactions stored in the database
+    pub async fn load(&self, db: &Database) -> Result<usize, ApiError> {
+        let pending = DbOps::get_pending_transactions(db).await?;
+        let count = pending.len();
+        self.restore(db, pending).await?;
+        self.expire(db).await?;
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MIN_FEE_RATE: f64 = 0.001;
+
+    fn transaction(hash: &str, sender: &str, inputs: &[&str], nonce: u64) -> Transaction {
+        Transaction {
+            id: None,
+            transaction_hash: hash.to_string(),
+            sender_wallet_id: sender.to_string(),
+            recipient_wallet_id: "recipient".to_string(),
+            amount: 1.0,
+            fee: 0.0,
+            note: None,
+            timestamp: Utc::now(),
+            sender_public_key: String::new(),
+            digital_signature: String::new(),
+            input_utxos: inputs.iter().map(|i| i.to_string()).collect(),
+            output_utxo: format!("{}-out", hash),
+            change_utxo: None,
+            outputs: Vec::new(),
+            chain_id: None,
+            nonce: Some(nonce),
+            status: TransactionStatus::InMempool,
+            block_hash: None,
+        }
+    }
+
+    // The entry's single output is "{hash}-out", so children spend that
+    fn entry(transaction: Transaction, fee: f64, size: usize) -> MempoolEntry {
+        let output = UTXO {
+            id: None,
+            utxo_id: transaction.output_utxo.clone(),
+            wallet_id: transaction.recipient_wallet_id.clone(),
+            amount: transaction.amount,
+            status: "unspent".to_string(),
+            block_hash: "pending".to_string(),
+            transaction_hash: transaction.transaction_hash.clone(),
+            created_at: Utc::now(),
+        };
+        MempoolEntry {
+            transaction,
+            outputs: vec![output],
+            fee,
+            size,
+            added_at: Utc::now(),
+        }
+    }
+
+    fn pool(max_bytes: usize, entries: Vec<MempoolEntry>) -> Mempool {
+        let mempool = Mempool::new(max_bytes, DEFAULT_MEMPOOL_EXPIRY_HOURS, MIN_FEE_RATE);
+        {
+            let mut state = mempool.state.lock().unwrap();
+            for entry in entries {
+                state.insert(entry);
+            }
+        }
+        mempool
+    }
+
+    fn replace(mempool: &Mempool, replacement: &MempoolEntry) -> Result<(), ApiError> {
+        let state = mempool.state.lock().unwrap();
+        let conflicts = Mempool::direct_conflicts(&state, &replacement.transaction);
+        mempool.check_replacement(&state, replacement, &conflicts)
+    }
+
+    fn hashes(transactions: &[Transaction]) -> Vec<&str> {
+        transactions.iter().map(|t| t.transaction_hash.as_str()).collect()
+    }
+
+    #[test]
+    fn replacement_must_pay_for_everything_it_replaces() {
+        let original = entry(transaction("a", "alice", &["u1"], 1), 1.0, 100);
+        let child = entry(transaction("b", "bob", &["a-out"], 1), 0.5, 100);
+        let mempool = pool(10_000, vec![original, child]);
+
+        // Covers the original but not its child, which is replaced along with it
+        let too_cheap = entry(transaction("c", "alice", &["u1"], 1), 1.2, 100);
+        assert!(replace(&mempool, &too_cheap).is_err());
+
+        let enough = entry(transaction("c", "alice", &["u1"], 1), 1.5 + MIN_FEE_RATE * 100.0, 100);
+        assert!(replace(&mempool, &enough).is_ok());
+    }
+
+    #[test]
+    fn replacement_must_raise_the_fee_rate() {
+        let mempool = pool(10_000, vec![entry(transaction("a", "alice", &["u1"], 1), 1.0, 100)]);
+
+        // Pays more in total, but spread over a much larger transaction
+        let larger = entry(transaction("c", "alice", &["u1", "u2"], 1), 2.0, 1_000);
+        assert!(replace(&mempool, &larger).is_err());
+
+        let denser = entry(transaction("c", "alice", &["u1"], 1), 2.0, 100);
+        assert!(replace(&mempool, &denser).is_ok());
+    }
+
+    #[test]
+    fn replacement_may_not_spend_what_it_replaces() {
+        let original = entry(transaction("a", "alice", &["u1"], 1), 1.0, 100);
+        let mempool = pool(10_000, vec![original]);
+
+        let spends_replaced = entry(transaction("c", "alice", &["u1", "a-out"], 1), 10.0, 100);
+        assert!(matches!(
+            replace(&mempool, &spends_replaced),
+            Err(ApiError::BadRequest(message)) if message.contains("spends")
+        ));
+    }
+
+    #[test]
+    fn full_pool_evicts_lowest_fee_rate_with_descendants() {
+        let low = entry(transaction("low", "alice", &["u1"], 1), 0.2, 100);
+        let low_child = entry(transaction("low-child", "bob", &["low-out"], 1), 0.9, 100);
+        let mid = entry(transaction("mid", "carol", &["u2"], 1), 0.5, 100);
+        let mempool = pool(300, vec![low, low_child, mid]);
+
+        let incoming = entry(transaction("new", "dave", &["u3"], 1), 0.6, 100);
+        let mut state = mempool.state.lock().unwrap();
+        let mut evicted = mempool.make_room(&mut state, &incoming).unwrap();
+        evicted.sort_by(|a, b| a.transaction_hash.cmp(&b.transaction_hash));
+
+        assert_eq!(hashes(&evicted), vec!["low", "low-child"]);
+        assert!(state.entries.contains_key("mid"));
+        assert_eq!(state.bytes, 100);
+    }
+
+    #[test]
+    fn full_pool_refuses_entries_paying_less_than_it_would_evict() {
+        let a = entry(transaction("a", "alice", &["u1"], 1), 0.5, 100);
+        let b = entry(transaction("b", "bob", &["u2"], 1), 0.7, 100);
+        let mempool = pool(200, vec![a, b]);
+
+        let incoming = entry(transaction("new", "carol", &["u3"], 1), 0.5, 100);
+        let mut state = mempool.state.lock().unwrap();
+        assert!(mempool.make_room(&mut state, &incoming).is_err());
+        assert_eq!(state.entries.len(), 2);
+    }
+
+    #[test]
+    fn eviction_skips_the_new_entrys_parents() {
+        let parent = entry(transaction("parent", "alice", &["u1"], 1), 0.1, 100);
+        let other = entry(transaction("other", "bob", &["u2"], 1), 0.3, 100);
+        let mempool = pool(200, vec![parent, other]);
+
+        let child = entry(transaction("child", "carol", &["parent-out"], 1), 0.5, 100);
+        let mut state = mempool.state.lock().unwrap();
+        let evicted = mempool.make_room(&mut state, &child).unwrap();
+        assert_eq!(hashes(&evicted), vec!["other"]);
+        assert!(state.entries.contains_key("parent"));
+    }
+
+    #[test]
+    fn block_selection_orders_by_fee_rate_then_arrival() {
+        let mut early = entry(transaction("early", "alice", &["u1"], 1), 0.5, 100);
+        let mut late = entry(transaction("late", "bob", &["u2"], 1), 0.5, 100);
+        early.added_at = Utc::now() - Duration::minutes(5);
+        late.added_at = Utc::now();
+        let best = entry(transaction("best", "carol", &["u3"], 1), 0.9, 100);
+        let mempool = pool(10_000, vec![late, best, early]);
+
+        assert_eq!(hashes(&mempool.select_for_block(10_000)), vec!["best", "early", "late"]);
+        // Only what fits, with a separator byte per transaction
+        assert_eq!(hashes(&mempool.select_for_block(203)), vec!["best", "early"]);
+    }
+
+    #[test]
+    fn block_selection_keeps_each_senders_nonces_in_order() {
+        let first = entry(transaction("first", "alice", &["u1"], 1), 0.1, 100);
+        let second = entry(transaction("second", "alice", &["u2"], 2), 0.9, 100);
+        let third = entry(transaction("third", "alice", &["u3"], 3), 0.5, 100);
+        let other = entry(transaction("other", "bob", &["u4"], 7), 0.3, 100);
+        let mempool = pool(10_000, vec![third, other, second, first]);
+
+        assert_eq!(hashes(&mempool.select_for_block(10_000)), vec!["other", "first", "second", "third"]);
+    }
+
+    #[test]
+    fn block_selection_places_parents_before_children() {
+        let parent = entry(transaction("parent", "alice", &["u1"], 1), 0.3, 300);
+        let child = entry(transaction("child", "bob", &["parent-out"], 1), 0.9, 100);
+        let mempool = pool(10_000, vec![child, parent]);
+
+        assert_eq!(hashes(&mempool.select_for_block(10_000)), vec!["parent", "child"]);
+        // A child whose parent does not fit is left out too
+        assert!(mempool.select_for_block(200).is_empty());
+    }
+}
@@ -1,6 +1,9 @@
 use serde::{Deserialize, Serialize};
 use chrono::{DateTime, Utc};
 use bson::oid::ObjectId;
+use std::fmt;
+
+use crate::coin_selection::CoinSelection;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct User {
@@ -14,10 +17,19 @@ pub struct User {
     pub public_key: String,
     pub private_key_encrypted: String,
     pub created_at: DateTime<Utc>,
-    pub beneficiaries: Vec<String>,
+    #[serde(default)]
+    pub beneficiaries: Vec<Beneficiary>,
     pub zakat_deduction: f64,
 }
 
+// A saved recipient in a user's address book
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Beneficiary {
+    pub wallet_id: String,
+    pub nickname: String,
+    pub created_at: DateTime<Utc>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct UTXO {
     #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
@@ -25,7 +37,7 @@ pub struct UTXO {
     pub utxo_id: String,
     pub wallet_id: String,
     pub amount: f64,
-    pub status: String, // "unspent" or "spent"
+    pub status: String, // "unspent", "spent" or "orphaned"
     pub block_hash: String,
     pub transaction_hash: String,
     pub created_at: DateTime<Utc>,
@@ -37,8 +49,13 @@ pub struct Transaction {
     pub id: Option<ObjectId>,
     pub transaction_hash: String,
     pub sender_wallet_id: String,
+    // With an outputs list these summarize it: the first recipient and the total paid excluding change
     pub recipient_wallet_id: String,
     pub amount: f64,
+    // Paid to the miner; inputs minus outputs must equal it. Omitted when zero so transactions in
+    // blocks hashed before fees existed serialize exactly as they did.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub fee: f64,
     pub note: Option<String>,
     pub timestamp: DateTime<Utc>,
     pub sender_public_key: String,
@@ -46,14 +63,84 @@ pub struct Transaction {
     pub input_utxos: Vec<String>,
     pub output_utxo: String,
     pub change_utxo: Option<String>,
-    pub status: String, // "pending" or "confirmed"
+    // Every output including change, signed as part of the transaction. Empty on transactions
+    // created before multi-output support, whose outputs are output_utxo and change_utxo.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub outputs: Vec<TxOutput>,
+    // Signed replay protection: the network the transaction is valid on and a per-sender
+    // sequence number. Absent on transactions created before it existed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chain_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nonce: Option<u64>,
+    pub status: TransactionStatus,
     pub block_hash: Option<String>,
 }
 
+// Serialized exactly as the strings stored before the enum, so older documents and legacy
+// block hashes are unaffected
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum TransactionStatus {
+    // Signed but not yet accepted into a mempool
+    Pending,
+    InMempool,
+    Confirmed,
+    // An input it spent no longer exists, e.g. after a conflicting block or a reorg
+    Orphaned,
+    // Valid but dropped by the mempool, on expiry or eviction for a low fee
+    Rejected,
+    // Superseded by a higher-fee transaction spending the same inputs
+    Replaced,
+}
+
+impl TransactionStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TransactionStatus::Pending => "pending",
+            TransactionStatus::InMempool => "in-mempool",
+            TransactionStatus::Confirmed => "confirmed",
+            TransactionStatus::Orphaned => "orphaned",
+            TransactionStatus::Rejected => "rejected",
+            TransactionStatus::Replaced => "replaced",
+        }
+    }
+
+    // Not yet mined, and may still be
+    pub fn is_unconfirmed(&self) -> bool {
+        matches!(self, TransactionStatus::Pending | TransactionStatus::InMempool)
+    }
+}
+
+impl fmt::Display for TransactionStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl Transaction {
+    pub fn output_ids(&self) -> Vec<String> {
+        if self.outputs.is_empty() {
+            std::iter::once(self.output_utxo.clone()).chain(self.change_utxo.clone()).collect()
+        } else {
+            self.outputs.iter().map(|o| o.utxo_id.clone()).collect()
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TxOutput {
+    pub utxo_id: String,
+    pub wallet_id: String,
+    pub amount: f64,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Block {
     #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
     pub id: Option<ObjectId>,
+    #[serde(default = "default_block_version")]
+    pub version: u32,
     pub index: u64,
     pub timestamp: DateTime<Utc>,
     pub transactions: Vec<Transaction>,
@@ -62,6 +149,52 @@ pub struct Block {
     pub hash: String,
     pub merkle_root: String,
     pub difficulty: u32,
+    // Local bookkeeping, not part of the block hash
+    #[serde(default = "default_main_chain")]
+    pub main_chain: bool,
+    #[serde(default)]
+    pub chain_work: u64,
+}
+
+fn default_main_chain() -> bool {
+    true
+}
+
+fn is_zero(value: &f64) -> bool {
+    *value == 0.0
+}
+
+// Blocks stored before versioning use the legacy merkle tree
+fn default_block_version() -> u32 {
+    1
+}
+
+// Block without its transactions; enough to check linkage and proof-of-work
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BlockHeader {
+    #[serde(default = "default_block_version")]
+    pub version: u32,
+    pub index: u64,
+    pub timestamp: DateTime<Utc>,
+    pub previous_hash: String,
+    pub nonce: u64,
+    pub hash: String,
+    pub merkle_root: String,
+    pub difficulty: u32,
+}
+
+// Initial block download progress, persisted so an interrupted sync resumes
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SyncProgress {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub peer: String,
+    pub best_header_hash: String,
+    pub best_header_height: u64,
+    pub bodies_height: u64,
+    pub complete: bool,
+    pub started_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -88,13 +221,130 @@ pub struct SystemLog {
     pub status: String,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SiblingPosition {
+    Left,
+    Right,
+}
+
+// One level of a merkle branch: the sibling hash and which side it sits on
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MerkleStep {
+    pub hash: String,
+    pub position: SiblingPosition,
+}
+
 // Request/Response DTOs
+#[derive(Debug, Deserialize)]
+pub struct HeadersQuery {
+    pub from: Option<u64>,
+    pub count: Option<i64>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    Asc,
+    #[default]
+    Desc,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HistoryDirection {
+    Sent,
+    Received,
+}
+
+// Filters and paging for a wallet's transaction history. `cursor` is the hash of the last
+// transaction on the previous page and is only meaningful with the same filters and sort.
+#[derive(Debug, Deserialize)]
+pub struct HistoryQuery {
+    pub cursor: Option<String>,
+    pub limit: Option<usize>,
+    #[serde(default)]
+    pub sort: SortOrder,
+    pub direction: Option<HistoryDirection>,
+    pub status: Option<TransactionStatus>,
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+    pub min_amount: Option<f64>,
+    pub max_amount: Option<f64>,
+    #[serde(default, deserialize_with = "crate::address::deserialize_optional_wallet_id")]
+    pub counterparty: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StatementFormat {
+    #[default]
+    Json,
+    Csv,
+    Ofx,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct StatementQuery {
+    pub from: DateTime<Utc>,
+    // Defaults to now
+    pub to: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub format: StatementFormat,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EntryType {
+    Credit,
+    Debit,
+}
+
+#[derive(Debug, Serialize)]
+pub struct StatementEntry {
+    pub date: DateTime<Utc>,
+    pub transaction_hash: String,
+    #[serde(rename = "type")]
+    pub entry_type: EntryType,
+    // "transfer", "zakat", "mining_reward" or "allocation"
+    pub category: String,
+    pub counterparty: String,
+    pub description: String,
+    pub amount: f64,
+    pub balance: f64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Statement {
+    pub wallet_id: String,
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+    pub generated_at: DateTime<Utc>,
+    pub opening_balance: f64,
+    pub total_credits: f64,
+    pub total_debits: f64,
+    pub closing_balance: f64,
+    pub entries: Vec<StatementEntry>,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct RegisterRequest {
     pub full_name: String,
     pub email: String,
     pub password: String,
     pub cnic: String,
+    // Optional BIP39 passphrase; the recovery phrase alone does not restore the wallet without it
+    pub passphrase: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RestoreWalletRequest {
+    pub mnemonic: String,
+    pub passphrase: Option<String>,
+    pub full_name: String,
+    pub email: String,
+    pub password: String,
+    pub cnic: String,
 }
 
 #[derive(Debug, Deserialize)]
@@ -113,6 +363,9 @@ pub struct VerifyOtpRequest {
 pub struct AuthResponse {
     pub token: String,
     pub user: UserResponse,
+    // Recovery phrase, only returned by register
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mnemonic: Option<String>,
 }
 
 #[derive(Debug, Serialize, Clone)]
@@ -120,26 +373,163 @@ pub struct UserResponse {
     pub email: String,
     pub full_name: String,
     pub wallet_id: String,
+    pub address: Option<String>,
     pub public_key: String,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct SendMoneyRequest {
+    #[serde(deserialize_with = "crate::address::deserialize_wallet_id")]
     pub sender_wallet_id: String,
+    #[serde(deserialize_with = "crate::address::deserialize_wallet_id")]
     pub recipient_wallet_id: String,
     pub amount: f64,
+    // Defaults to the current "normal" fee estimate
+    pub fee: Option<f64>,
     pub note: Option<String>,
+    #[serde(default)]
+    pub coin_selection: CoinSelection,
+}
+
+// Payment from a wallet whose key is held by the client rather than this node
+#[derive(Debug, Deserialize)]
+pub struct PrepareTransactionRequest {
+    pub sender_public_key: String,
+    pub payments: Vec<Payment>,
+    // Defaults to the current "normal" fee estimate
+    pub fee: Option<f64>,
+    pub note: Option<String>,
+    #[serde(default)]
+    pub coin_selection: CoinSelection,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SubmitTransactionRequest {
+    pub transaction: Transaction,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AddBeneficiaryRequest {
+    #[serde(deserialize_with = "crate::address::deserialize_wallet_id")]
+    pub wallet_id: String,
+    pub nickname: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RenameBeneficiaryRequest {
+    pub nickname: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SendToBeneficiaryRequest {
+    #[serde(deserialize_with = "crate::address::deserialize_wallet_id")]
+    pub sender_wallet_id: String,
+    // Nickname or wallet ID of a saved beneficiary
+    pub beneficiary: String,
+    pub amount: f64,
+    pub fee: Option<f64>,
+    pub note: Option<String>,
+    #[serde(default)]
+    pub coin_selection: CoinSelection,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Payment {
+    #[serde(deserialize_with = "crate::address::deserialize_wallet_id")]
+    pub recipient_wallet_id: String,
+    pub amount: f64,
+}
+
+// JSON body of a batch payment; CSV batches pass sender, fee and note as query parameters
+#[derive(Debug, Deserialize)]
+pub struct BatchPaymentRequest {
+    #[serde(deserialize_with = "crate::address::deserialize_wallet_id")]
+    pub sender_wallet_id: String,
+    pub payments: Vec<Payment>,
+    pub fee: Option<f64>,
+    pub note: Option<String>,
+    #[serde(default)]
+    pub coin_selection: CoinSelection,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BatchPaymentQuery {
+    #[serde(deserialize_with = "crate::address::deserialize_wallet_id")]
+    pub sender_wallet_id: String,
+    pub fee: Option<f64>,
+    pub note: Option<String>,
+    #[serde(default)]
+    pub coin_selection: CoinSelection,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ConsolidateRequest {
+    // Only outputs worth at most this are merged; defaults to every unspent output
+    pub max_utxo_amount: Option<f64>,
+    pub max_inputs: Option<usize>,
+    // When the "slow" fee rate is above this, the consolidation waits until it drops
+    pub max_fee_rate: Option<f64>,
+}
+
+// A consolidation waiting for fees to fall; at most one per wallet
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScheduledConsolidation {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub wallet_id: String,
+    pub max_utxo_amount: Option<f64>,
+    pub max_inputs: Option<usize>,
+    pub max_fee_rate: f64,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ReplaceTransactionRequest {
+    #[serde(deserialize_with = "crate::address::deserialize_wallet_id")]
+    pub sender_wallet_id: String,
+    // Defaults to the least the replacement rules allow, or the "normal" estimate if higher
+    pub fee: Option<f64>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CancelTransactionRequest {
+    #[serde(deserialize_with = "crate::address::deserialize_wallet_id")]
+    pub sender_wallet_id: String,
+}
+
+// Outcome of a send stored under its Idempotency-Key, so a retry returns it instead of paying again
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IdempotencyRecord {
+    // "{sender_wallet_id}:{key}", so keys are scoped to the sending wallet
+    #[serde(rename = "_id")]
+    pub id: String,
+    // Hash of the request body; a key may not be reused for a different request
+    pub request_hash: String,
+    // None while the first request is still being processed
+    pub transaction_hash: Option<String>,
+    pub created_at: DateTime<Utc>,
+    // Stored as a BSON date so the TTL index on it removes the record once it expires
+    pub expires_at: mongodb::bson::DateTime,
 }
 
 #[derive(Debug, Serialize)]
 pub struct TransactionResponse {
     pub transaction_hash: String,
-    pub status: String,
+    pub status: TransactionStatus,
 }
 
 #[derive(Debug, Serialize)]
 pub struct BalanceResponse {
+    // Every unspent output, confirmed or not
     pub balance: f64,
+    pub confirmed: f64,
+    // Unconfirmed outputs paid to this wallet by others
+    pub pending_incoming: f64,
+    // Payments and fees of this wallet's unconfirmed transactions, excluding change
+    pub pending_outgoing: f64,
+    // Outputs with at least min_confirmations confirmations
+    pub spendable: f64,
+    pub min_confirmations: u64,
     pub utxos: Vec<UtxoResponse>,
     pub zakat_deduction: f64,
 }
@@ -150,4 +540,49 @@ pub struct UtxoResponse {
     pub amount: f64,
     pub status: String,
     pub block_hash: String,
+    pub confirmations: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MerkleProofResponse {
+    pub transaction_hash: String,
+    pub block_hash: String,
+    pub block_index: u64,
+    pub block_version: u32,
+    pub merkle_root: String,
+    pub branch: Vec<MerkleStep>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct VerifyProofRequest {
+    pub transaction_hash: String,
+    pub merkle_root: String,
+    pub branch: Vec<MerkleStep>,
+    #[serde(default = "default_block_version")]
+    pub block_version: u32,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MineRequest {
+    #[serde(default, deserialize_with = "crate::address::deserialize_optional_wallet_id")]
+    pub miner_wallet_id: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FeeRates {
+    pub fast: f64,
+    pub normal: f64,
+    pub slow: f64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FeeEstimate {
+    pub min_relay_fee_rate: f64,
+    // Per byte of serialized transaction
+    pub fee_rates: FeeRates,
+    // Fees for a transaction of typical_size bytes
+    pub fees: FeeRates,
+    pub typical_size: usize,
+    pub blocks_sampled: usize,
+    pub transactions_sampled: usize,
 }
@@ -0,0 +1,445 @@
+use crate::blockchain::{Blockchain, HEADER_HASH_VERSION};
+use crate::chain::{BlockOutcome, ChainManager};
+use crate::db::Database as DbOps;
+use crate::error::ApiError;
+use crate::mempool::Mempool;
+use crate::models::{Block, BlockHeader, SyncProgress, Transaction, UTXO};
+use chrono::{DateTime, Utc};
+use mongodb::Database;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+pub const PROTOCOL_VERSION: u32 = 2;
+pub const MAX_HEADERS_PER_MESSAGE: i64 = 2000;
+pub const MAX_BLOCKS_PER_MESSAGE: usize = 16;
+// Body requests in flight at once during initial block download
+const PARALLEL_BODY_REQUESTS: usize = 4;
+
+// Exchanged on handshake so both sides know they are on the same network and who is ahead
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VersionMessage {
+    pub protocol_version: u32,
+    pub node_url: String,
+    pub genesis_hash: String,
+    pub chain_id: String,
+    pub best_height: u64,
+    pub best_hash: String,
+}
+
+// Blocks and transactions travel with the outputs they create, since amounts live in UTXO documents
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BlockMessage {
+    pub sender: String,
+    pub block: Block,
+    pub outputs: Vec<UTXO>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TransactionMessage {
+    pub sender: String,
+    pub transaction: Transaction,
+    pub outputs: Vec<UTXO>,
+}
+
+// Hashes of our main chain, dense near the tip and sparse towards genesis
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GetHeadersRequest {
+    pub locator: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GetDataRequest {
+    pub hashes: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PeerInfo {
+    pub url: String,
+    pub best_height: u64,
+    pub last_seen: DateTime<Utc>,
+}
+
+pub struct PeerManager {
+    pub node_url: String,
+    peers: Mutex<HashMap<String, PeerInfo>>,
+    // Blocks downloaded during sync update the mempool like any other block
+    mempool: Arc<Mempool>,
+    client: reqwest::Client,
+    // Only one block download runs at a time, since they share the persisted sync progress
+    syncing: AtomicBool,
+}
+
+impl PeerManager {
+    pub fn new(node_url: String, mempool: Arc<Mempool>) -> Self {
+        PeerManager {
+            node_url,
+            peers: Mutex::new(HashMap::new()),
+            mempool,
+            client: reqwest::Client::builder()
+                .timeout(Duration::from_secs(10))
+                .build()
+                .expect("Failed to build HTTP client"),
+            syncing: AtomicBool::new(false),
+        }
+    }
+
+    pub fn peers(&self) -> Vec<PeerInfo> {
+        self.peers.lock().unwrap().values().cloned().collect()
+    }
+
+    pub fn peer_urls(&self) -> Vec<String> {
+        self.peers.lock().unwrap().keys().cloned().collect()
+    }
+
+    pub fn is_peer(&self, url: &str) -> bool {
+        self.peers.lock().unwrap().contains_key(url)
+    }
+
+    pub fn is_syncing(&self) -> bool {
+        self.syncing.load(Ordering::SeqCst)
+    }
+
+    pub async fn local_version(&self, db: &Database) -> Result<VersionMessage, ApiError> {
+        let tip = DbOps::get_chain_tip(db).await?;
+        Ok(VersionMessage {
+            protocol_version: PROTOCOL_VERSION,
+            node_url: self.node_url.clone(),
+            genesis_hash: Blockchain::create_genesis_block().hash,
+            chain_id: Blockchain::chain_id(),
+            best_height: tip.index,
+            best_hash: tip.hash,
+        })
+    }
+
+    // Registers a peer after checking it speaks our protocol on the same chain
+    pub fn register_peer(&self, version: &VersionMessage) -> Result<(), ApiError> {
+        if version.protocol_version != PROTOCOL_VERSION {
+            return Err(ApiError::BadRequest(format!(
+                "Unsupported protocol version {}",
+                version.protocol_version
+            )));
+        }
+        if version.genesis_hash != Blockchain::create_genesis_block().hash || version.chain_id != Blockchain::chain_id() {
+            return Err(ApiError::BadRequest("Peer is on a different network".to_string()));
+        }
+        if version.node_url.is_empty() || version.node_url == self.node_url {
+            return Ok(());
+        }
+
+        self.peers.lock().unwrap().insert(
+            version.node_url.clone(),
+            PeerInfo {
+                url: version.node_url.clone(),
+                best_height: version.best_height,
+                last_seen: Utc::now(),
+            },
+        );
+        Ok(())
+    }
+
+    pub async fn handshake(&self, db: &Database, peer_url: &str) -> Result<VersionMessage, ApiError> {
+        let ours = self.local_version(db).await?;
+        let theirs: VersionMessage = self
+            .client
+            .post(format!("{}/api/p2p/version", peer_url))
+            .json(&ours)
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map_err(|e| ApiError::InternalError(format!("Handshake with {} failed: {}", peer_url, e)))?
+            .json()
+            .await
+            .map_err(|e| ApiError::InternalError(e.to_string()))?;
+
+        // Peers may advertise a different URL than the one we dialed; remember the one we can reach
+        let mut version = theirs.clone();
+        version.node_url = peer_url.to_string();
+        self.register_peer(&version)?;
+        Ok(theirs)
+    }
+
+    // Headers-first initial block download: fetch and check the header chain, then the bodies in
+    // parallel batches. Progress is persisted, so an interrupted sync picks up where it stopped.
+    pub async fn sync_from_peer(&self, db: &Database, peer_url: &str) -> Result<usize, ApiError> {
+        if self.syncing.swap(true, Ordering::SeqCst) {
+            return Err(ApiError::Conflict("A block download is already running".to_string()));
+        }
+        let result = self.download_chain(db, peer_url).await;
+        self.syncing.store(false, Ordering::SeqCst);
+        result
+    }
+
+    async fn download_chain(&self, db: &Database, peer_url: &str) -> Result<usize, ApiError> {
+        let mut progress = match DbOps::get_sync_progress(db).await? {
+            Some(progress) if !progress.complete && progress.peer == peer_url => progress,
+            previous => {
+                // Headers from another peer may be on a different fork, so they are not reused
+                if previous.is_some_and(|p| !p.complete) {
+                    DbOps::clear_sync_headers(db).await?;
+                }
+                SyncProgress {
+                    id: None,
+                    peer: peer_url.to_string(),
+                    best_header_hash: String::new(),
+                    best_header_height: 0,
+                    bodies_height: 0,
+                    complete: false,
+                    started_at: Utc::now(),
+                    updated_at: Utc::now(),
+                }
+            }
+        };
+
+        self.download_headers(db, peer_url, &mut progress).await?;
+        let imported = self.download_bodies(db, peer_url, &mut progress).await?;
+
+        progress.complete = true;
+        progress.updated_at = Utc::now();
+        DbOps::save_sync_progress(db, &progress).await?;
+        DbOps::clear_sync_headers(db).await?;
+        Ok(imported)
+    }
+
+    async fn download_headers(
+        &self,
+        db: &Database,
+        peer_url: &str,
+        progress: &mut SyncProgress,
+    ) -> Result<(), ApiError> {
+        loop {
+            // Continue after the last header we stored, falling back to our own chain
+            let mut locator = Vec::new();
+            if !progress.best_header_hash.is_empty() {
+                locator.push(progress.best_header_hash.clone());
+            }
+            locator.extend(block_locator(&DbOps::get_main_chain_headers(db).await?));
+
+            let headers: Vec<BlockHeader> = self
+                .client
+                .post(format!("{}/api/p2p/getheaders", peer_url))
+                .json(&GetHeadersRequest { locator })
+                .send()
+                .await
+                .and_then(|r| r.error_for_status())
+                .map_err(|e| ApiError::InternalError(format!("Header sync from {} failed: {}", peer_url, e)))?
+                .json()
+                .await
+                .map_err(|e| ApiError::InternalError(e.to_string()))?;
+
+            let first = match headers.first() {
+                Some(first) => first,
+                None => return Ok(()),
+            };
+            let mut previous = match DbOps::get_sync_header(db, &first.previous_hash).await? {
+                Some(header) => header,
+                None => DbOps::get_block_by_hash(db, &first.previous_hash)
+                    .await?
+                    .map(|b| Blockchain::block_header(&b))
+                    .ok_or_else(|| ApiError::BadRequest(format!("Headers from {} do not connect", peer_url)))?,
+            };
+            for header in &headers {
+                Blockchain::check_header(header, &previous)?;
+                previous = header.clone();
+            }
+            self.verify_legacy_headers(peer_url, &headers).await?;
+
+            DbOps::save_sync_headers(db, &headers).await?;
+            progress.best_header_hash = previous.hash.clone();
+            progress.best_header_height = previous.index;
+            progress.updated_at = Utc::now();
+            DbOps::save_sync_progress(db, progress).await?;
+            log::info!("Downloaded headers up to height {} from {}", previous.index, peer_url);
+
+            if (headers.len() as i64) < MAX_HEADERS_PER_MESSAGE {
+                return Ok(());
+            }
+        }
+    }
+
+    // Headers older than HEADER_HASH_VERSION only hash correctly together with their body, so their
+    // bodies are fetched and checked before the headers are trusted
+    async fn verify_legacy_headers(&self, peer_url: &str, headers: &[BlockHeader]) -> Result<(), ApiError> {
+        let legacy: Vec<&BlockHeader> = headers.iter().filter(|h| h.version < HEADER_HASH_VERSION).collect();
+        for batch in legacy.chunks(MAX_BLOCKS_PER_MESSAGE) {
+            let messages = self
+                .fetch_blocks(peer_url, batch.iter().map(|h| h.hash.clone()).collect())
+                .await?;
+            for header in batch {
+                let block = messages
+                    .iter()
+                    .map(|m| &m.block)
+                    .find(|b| b.hash == header.hash)
+                    .ok_or_else(|| ApiError::BadRequest(format!("Peer did not send block {}", header.hash)))?;
+                Blockchain::check_block(block)?;
+                let body = Blockchain::block_header(block);
+                if body.index != header.index
+                    || body.previous_hash != header.previous_hash
+                    || body.version != header.version
+                {
+                    return Err(ApiError::BadRequest(format!("Block {} does not match its header", header.hash)));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn download_bodies(
+        &self,
+        db: &Database,
+        peer_url: &str,
+        progress: &mut SyncProgress,
+    ) -> Result<usize, ApiError> {
+        let mut imported = 0;
+        loop {
+            let wanted = DbOps::get_sync_headers_above(
+                db,
+                progress.bodies_height,
+                (MAX_BLOCKS_PER_MESSAGE * PARALLEL_BODY_REQUESTS) as i64,
+            )
+            .await?;
+            if wanted.is_empty() {
+                return Ok(imported);
+            }
+
+            let batches: Vec<&[BlockHeader]> = wanted.chunks(MAX_BLOCKS_PER_MESSAGE).collect();
+            let responses = futures::future::join_all(
+                batches
+                    .iter()
+                    .map(|batch| self.fetch_blocks(peer_url, batch.iter().map(|h| h.hash.clone()).collect())),
+            )
+            .await;
+
+            // Bodies arrive in parallel but are connected strictly in height order
+            for (batch, response) in batches.iter().zip(responses) {
+                let mut messages = response?;
+                for header in batch.iter() {
+                    let position = messages
+                        .iter()
+                        .position(|m| m.block.hash == header.hash)
+                        .ok_or_else(|| ApiError::BadRequest(format!("Peer did not send block {}", header.hash)))?;
+                    let message = messages.swap_remove(position);
+                    if message.block.index != header.index || message.block.previous_hash != header.previous_hash {
+                        return Err(ApiError::BadRequest(format!("Block {} does not match its header", header.hash)));
+                    }
+
+                    if !matches!(import_block(db, &self.mempool, message).await?, BlockOutcome::AlreadyKnown) {
+                        imported += 1;
+                    }
+                    progress.bodies_height = header.index;
+                }
+                progress.updated_at = Utc::now();
+                DbOps::save_sync_progress(db, progress).await?;
+            }
+        }
+    }
+
+    async fn fetch_blocks(&self, peer_url: &str, hashes: Vec<String>) -> Result<Vec<BlockMessage>, ApiError> {
+        self.client
+            .post(format!("{}/api/p2p/getdata", peer_url))
+            .json(&GetDataRequest { hashes })
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map_err(|e| ApiError::InternalError(format!("Block download from {} failed: {}", peer_url, e)))?
+            .json()
+            .await
+            .map_err(|e| ApiError::InternalError(e.to_string()))
+    }
+
+    // Handshakes with the configured peers, resumes an interrupted sync and catches up with any peer that is ahead
+    pub async fn start(&self, db: &Database, seeds: &[String]) {
+        let mut seeds = seeds.to_vec();
+        if let Ok(Some(progress)) = DbOps::get_sync_progress(db).await {
+            if !progress.complete {
+                log::info!("Resuming block download from {} at height {}", progress.peer, progress.bodies_height);
+                seeds.retain(|s| *s != progress.peer);
+                seeds.insert(0, progress.peer);
+            }
+        }
+
+        for seed in &seeds {
+            match self.handshake(db, seed).await {
+                Ok(version) => {
+                    let local_height = DbOps::get_chain_tip(db).await.map(|b| b.index).unwrap_or(0);
+                    let resuming = matches!(DbOps::get_sync_progress(db).await, Ok(Some(p)) if !p.complete);
+                    if version.best_height > local_height || resuming {
+                        match self.sync_from_peer(db, seed).await {
+                            Ok(n) => log::info!("Synced {} blocks from {}", n, seed),
+                            Err(e) => log::warn!("{}", e),
+                        }
+                    }
+                }
+                Err(e) => log::warn!("{}", e),
+            }
+        }
+    }
+
+    pub async fn broadcast_block(&self, db: &Database, block: &Block) {
+        let message = match block_message(db, &self.node_url, block.clone()).await {
+            Ok(message) => message,
+            Err(e) => return log::warn!("Failed to build block announcement: {}", e),
+        };
+        for peer in self.peer_urls() {
+            let url = format!("{}/api/p2p/blocks", peer);
+            if let Err(e) = self.client.post(&url).json(&message).send().await {
+                log::warn!("Failed to announce block to {}: {}", peer, e);
+            }
+        }
+    }
+
+    pub async fn broadcast_transaction(&self, db: &Database, tx: &Transaction) {
+        let outputs = match DbOps::get_utxos_by_transaction_hashes(db, std::slice::from_ref(&tx.transaction_hash)).await {
+            Ok(outputs) => outputs,
+            Err(e) => return log::warn!("Failed to build transaction announcement: {}", e),
+        };
+        let message = TransactionMessage {
+            sender: self.node_url.clone(),
+            transaction: tx.clone(),
+            outputs,
+        };
+        for peer in self.peer_urls() {
+            let url = format!("{}/api/p2p/transactions", peer);
+            if let Err(e) = self.client.post(&url).json(&message).send().await {
+                log::warn!("Failed to announce transaction to {}: {}", peer, e);
+            }
+        }
+    }
+}
+
+// Every hash for the last ten blocks, then doubling steps back to genesis
+pub fn block_locator(main_chain: &[BlockHeader]) -> Vec<String> {
+    let mut locator = Vec::new();
+    let mut step = 1;
+    let mut i = main_chain.len() as i64 - 1;
+    while i >= 0 {
+        locator.push(main_chain[i as usize].hash.clone());
+        if locator.len() >= 10 {
+            step *= 2;
+        }
+        i -= step;
+    }
+    if let Some(genesis) = main_chain.first() {
+        if locator.last() != Some(&genesis.hash) {
+            locator.push(genesis.hash.clone());
+        }
+    }
+    locator
+}
+
+pub async fn block_message(db: &Database, sender: &str, block: Block) -> Result<BlockMessage, ApiError> {
+    let tx_hashes: Vec<String> = block.transactions.iter().map(|tx| tx.transaction_hash.clone()).collect();
+    let outputs = DbOps::get_utxos_by_transaction_hashes(db, &tx_hashes).await?;
+    Ok(BlockMessage {
+        sender: sender.to_string(),
+        block,
+        outputs,
+    })
+}
+
+// Hands a peer's block and the outputs it creates to chain selection
+pub async fn import_block(db: &Database, mempool: &Mempool, message: BlockMessage) -> Result<BlockOutcome, ApiError> {
+    ChainManager::process_block(db, mempool, message.block, message.outputs).await
+}
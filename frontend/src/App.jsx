@@ -6,6 +6,7 @@ import Navbar from "./components/Navbar"
 import Dashboard from "./pages/Dashboard"
 import Login from "./pages/Login"
 import Register from "./pages/Register"
+import Restore from "./pages/Restore"
 import SendMoney from "./pages/SendMoney"
 import BlockExplorer from "./pages/BlockExplorer"
 import TransactionHistory from "./pages/TransactionHistory"
@@ -40,8 +41,15 @@ const App = () => {
   }
 
   if (!isLoggedIn) {
+    if (currentPage === "restore") {
+      return <Restore onRestore={handleLogin} onSwitchToLogin={() => setCurrentPage("login")} />
+    }
     return currentPage === "login" ? (
-      <Login onLogin={handleLogin} onSwitchToRegister={() => setCurrentPage("register")} />
+      <Login
+        onLogin={handleLogin}
+        onSwitchToRegister={() => setCurrentPage("register")}
+        onSwitchToRestore={() => setCurrentPage("restore")}
+      />
     ) : (
       <Register onRegister={handleLogin} onSwitchToLogin={() => setCurrentPage("login")} />
     )
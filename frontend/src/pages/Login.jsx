@@ -3,7 +3,7 @@
 import { useState } from "react"
 import "../styles/Auth.css"
 
-const Login = ({ onLogin, onSwitchToRegister }) => {
+const Login = ({ onLogin, onSwitchToRegister, onSwitchToRestore }) => {
   const [email, setEmail] = useState("")
   const [password, setPassword] = useState("")
   const [otp, setOtp] = useState("")
@@ -97,6 +97,9 @@ const Login = ({ onLogin, onSwitchToRegister }) => {
         <p className="auth-link">
           Don't have an account? <a onClick={onSwitchToRegister}>Register here</a>
         </p>
+        <p className="auth-link">
+          Lost access to your account? <a onClick={onSwitchToRestore}>Restore from recovery phrase</a>
+        </p>
       </div>
     </div>
   )
@@ -10,9 +10,11 @@ const Register = ({ onRegister, onSwitchToLogin }) => {
     password: "",
     confirmPassword: "",
     cnic: "",
+    passphrase: "",
   })
   const [error, setError] = useState("")
   const [loading, setLoading] = useState(false)
+  const [registered, setRegistered] = useState(null)
 
   const handleChange = (e) => {
     setFormData({
@@ -41,6 +43,7 @@ const Register = ({ onRegister, onSwitchToLogin }) => {
           email: formData.email,
           password: formData.password,
           cnic: formData.cnic,
+          passphrase: formData.passphrase || null,
         }),
       })
 
@@ -48,7 +51,12 @@ const Register = ({ onRegister, onSwitchToLogin }) => {
         const data = await response.json()
         localStorage.setItem("authToken", data.token)
         localStorage.setItem("userData", JSON.stringify(data.user))
-        onRegister(data.user)
+        // The recovery phrase is only returned once, so show it before continuing
+        if (data.mnemonic) {
+          setRegistered(data)
+        } else {
+          onRegister(data.user)
+        }
       } else {
         setError("Registration failed")
       }
@@ -59,6 +67,22 @@ const Register = ({ onRegister, onSwitchToLogin }) => {
     }
   }
 
+  if (registered) {
+    return (
+      <div className="auth-container">
+        <div className="auth-card">
+          <h2>Your Recovery Phrase</h2>
+          <p>
+            Write these words down in order and keep them somewhere safe. They are the only way to restore your wallet
+            and will not be shown again.
+          </p>
+          <div className="mnemonic">{registered.mnemonic}</div>
+          <button onClick={() => onRegister(registered.user)}>I have written it down</button>
+        </div>
+      </div>
+    )
+  }
+
   return (
     <div className="auth-container">
       <div className="auth-card">
@@ -92,6 +116,11 @@ const Register = ({ onRegister, onSwitchToLogin }) => {
               required
             />
           </div>
+          <div className="form-group">
+            <label>Recovery Passphrase (optional)</label>
+            <input type="password" name="passphrase" value={formData.passphrase} onChange={handleChange} />
+            <small>Protects your recovery phrase. It cannot be reset: without it the phrase restores a different, empty wallet.</small>
+          </div>
           <button type="submit" disabled={loading}>
             {loading ? "Registering..." : "Register"}
           </button>
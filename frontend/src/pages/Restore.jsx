@@ -0,0 +1,122 @@
+"use client"
+
+import { useState } from "react"
+import "../styles/Auth.css"
+
+const Restore = ({ onRestore, onSwitchToLogin }) => {
+  const [formData, setFormData] = useState({
+    mnemonic: "",
+    passphrase: "",
+    fullName: "",
+    email: "",
+    password: "",
+    confirmPassword: "",
+    cnic: "",
+  })
+  const [error, setError] = useState("")
+  const [loading, setLoading] = useState(false)
+
+  const handleChange = (e) => {
+    setFormData({
+      ...formData,
+      [e.target.name]: e.target.value,
+    })
+  }
+
+  const handleSubmit = async (e) => {
+    e.preventDefault()
+    setError("")
+    setLoading(true)
+
+    if (formData.password !== formData.confirmPassword) {
+      setError("Passwords do not match")
+      setLoading(false)
+      return
+    }
+
+    try {
+      const response = await fetch("http://localhost:3001/api/auth/restore", {
+        method: "POST",
+        headers: { "Content-Type": "application/json" },
+        body: JSON.stringify({
+          mnemonic: formData.mnemonic,
+          passphrase: formData.passphrase || null,
+          full_name: formData.fullName,
+          email: formData.email,
+          password: formData.password,
+          cnic: formData.cnic,
+        }),
+      })
+
+      if (response.ok) {
+        const data = await response.json()
+        localStorage.setItem("authToken", data.token)
+        localStorage.setItem("userData", JSON.stringify(data.user))
+        onRestore(data.user)
+      } else if (response.status === 400) {
+        setError("Invalid recovery phrase")
+      } else {
+        setError("Restore failed")
+      }
+    } catch (err) {
+      setError("Restore error. Please try again.")
+    } finally {
+      setLoading(false)
+    }
+  }
+
+  return (
+    <div className="auth-container">
+      <div className="auth-card">
+        <h2>Restore Your Wallet</h2>
+        {error && <div className="error-message">{error}</div>}
+
+        <form onSubmit={handleSubmit}>
+          <div className="form-group">
+            <label>Recovery Phrase</label>
+            <textarea name="mnemonic" rows={4} value={formData.mnemonic} onChange={handleChange} required />
+          </div>
+          <div className="form-group">
+            <label>Passphrase (only if you set one at registration)</label>
+            <input type="password" name="passphrase" value={formData.passphrase} onChange={handleChange} />
+          </div>
+          <div className="form-group">
+            <label>Full Name</label>
+            <input type="text" name="fullName" value={formData.fullName} onChange={handleChange} required />
+          </div>
+          <div className="form-group">
+            <label>Email</label>
+            <input type="email" name="email" value={formData.email} onChange={handleChange} required />
+          </div>
+          <div className="form-group">
+            <label>CNIC / National ID</label>
+            <input type="text" name="cnic" value={formData.cnic} onChange={handleChange} required />
+          </div>
+          <div className="form-group">
+            <label>New Password</label>
+            <input type="password" name="password" value={formData.password} onChange={handleChange} required />
+          </div>
+          <div className="form-group">
+            <label>Confirm Password</label>
+            <input
+              type="password"
+              name="confirmPassword"
+              value={formData.confirmPassword}
+              onChange={handleChange}
+              required
+            />
+          </div>
+          <button type="submit" disabled={loading}>
+            {loading ? "Restoring..." : "Restore Wallet"}
+          </button>
+        </form>
+
+        <p className="auth-link">
+          Remembered your password? <a onClick={onSwitchToLogin}>Login here</a>
+        </p>
+      </div>
+    </div>
+  )
+}
+
+export default Restore
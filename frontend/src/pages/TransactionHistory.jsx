@@ -5,22 +5,25 @@ import "../styles/History.css"
 
 const TransactionHistory = ({ user }) => {
   const [transactions, setTransactions] = useState([])
+  const [nextCursor, setNextCursor] = useState(null)
   const [loading, setLoading] = useState(true)
 
   useEffect(() => {
     fetchTransactions()
   }, [user])
 
-  const fetchTransactions = async () => {
+  const fetchTransactions = async (cursor = null) => {
     try {
       const token = localStorage.getItem("authToken")
-      const response = await fetch(`http://localhost:3001/api/transactions/history/${user.walletId}`, {
+      const query = cursor ? `?cursor=${encodeURIComponent(cursor)}` : ""
+      const response = await fetch(`http://localhost:3001/api/transactions/history/${user.walletId}${query}`, {
         headers: { Authorization: `Bearer ${token}` },
       })
 
       if (response.ok) {
         const data = await response.json()
-        setTransactions(data)
+        setTransactions((previous) => (cursor ? [...previous, ...data.transactions] : data.transactions))
+        setNextCursor(data.next_cursor)
       }
     } catch (err) {
       console.error("Failed to fetch transactions", err)
@@ -50,12 +53,12 @@ const TransactionHistory = ({ user }) => {
             </thead>
             <tbody>
               {transactions.map((tx) => (
-                <tr key={tx.id}>
+                <tr key={tx.hash}>
                   <td>
                     <span className={`type ${tx.type}`}>{tx.type}</span>
                   </td>
                   <td>{tx.amount} CWC</td>
-                  <td>{tx.counterparty}</td>
+                  <td>{(tx.counterparties || [tx.counterparty]).join(", ")}</td>
                   <td>{new Date(tx.timestamp).toLocaleString()}</td>
                   <td>
                     <span className={`status ${tx.status}`}>{tx.status}</span>
@@ -68,6 +71,12 @@ const TransactionHistory = ({ user }) => {
         ) : (
           <p className="no-data">No transactions yet</p>
         )}
+
+        {nextCursor && (
+          <button className="load-more" onClick={() => fetchTransactions(nextCursor)}>
+            Load more
+          </button>
+        )}
       </div>
     </div>
   )